@@ -0,0 +1,99 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+
+// What a lock does each time it sees the lock word still taken.
+// A fresh value ( Default ) is created for every acquisition so it can keep state.
+pub trait Backoff: Default {
+    fn snooze(&mut self);
+}
+
+// past this step the exponential ones stop growing ( 2^6 = 64 spin hints )
+const MAX_STEP: u32 = 6;
+
+// Plain busy-wait with a spin_loop hint ( PAUSE on x86, YIELD on arm )
+#[derive(Default)]
+pub struct Spin;
+
+impl Backoff for Spin {
+    fn snooze(&mut self) {
+        std::hint::spin_loop();
+    }
+}
+
+// Doubles the number of spin hints every time, fewer reads of the contended cache line
+#[derive(Default)]
+pub struct Exponential {
+    step: u32,
+}
+
+impl Backoff for Exponential {
+    fn snooze(&mut self) {
+        for _ in 0..1 << self.step {
+            std::hint::spin_loop();
+        }
+        if self.step < MAX_STEP {
+            self.step += 1;
+        }
+    }
+}
+
+// Exponential spinning first, after that give the core away to the OS scheduler.
+// Good when there are more threads than cores and the holder may be descheduled.
+#[derive(Default)]
+pub struct SpinThenYield {
+    step: u32,
+}
+
+impl Backoff for SpinThenYield {
+    fn snooze(&mut self) {
+        if self.step < MAX_STEP {
+            for _ in 0..1 << self.step {
+                std::hint::spin_loop();
+            }
+            self.step += 1;
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+// Like Exponential but spins a random amount up to the current limit,
+// so waiters that failed together don't all come back at the same moment
+#[derive(Default)]
+pub struct Jitter {
+    step: u32,
+}
+
+impl Backoff for Jitter {
+    fn snooze(&mut self) {
+        let limit = 1u64 << self.step;
+        for _ in 0..=random() % limit {
+            std::hint::spin_loop();
+        }
+        if self.step < MAX_STEP {
+            self.step += 1;
+        }
+    }
+}
+
+// xorshift64, quality doesn't matter much here, it just has to be cheap
+fn random() -> u64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new({
+            // RandomState is seeded per thread by std, good enough as a seed
+            let mut h = RandomState::new().build_hasher();
+            h.write_u8(0);
+            h.finish() | 1
+        });
+    }
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    })
+}
@@ -0,0 +1,260 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backoff::{Exponential, Jitter, SpinThenYield};
+use crate::clh::{RawClhLock, RawClhTimeoutLock};
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use crate::futex::RawFutexLock;
+use crate::mcs::RawMcsLock;
+use crate::mutex::{Mutex, TasLock, TtasLock};
+use crate::raw::RawLock;
+use crate::ticket::RawTicketLock;
+
+// Numbers for every RawLock behind Mutex :
+// - latency : one thread, nobody else around, ns per lock + unlock
+// - throughput : t threads hammer one lock for a fixed time, acquisitions per second
+// - fairness : from the same run, how evenly those acquisitions were spread over the
+//   threads ( coefficient of variation, 0 = perfectly even, unfair locks let one
+//   thread keep re-taking the lock while it's hot in its cache )
+// Everything goes to <prefix>.csv ( one row per measurement ) and <prefix>.json.
+
+pub struct Config {
+    // 1, 2, 4, .. up to this many threads
+    pub max_threads: usize,
+    pub duration: Duration,
+    // lock + unlock pairs for the latency test
+    pub iterations: usize,
+}
+
+pub struct Row {
+    pub lock: &'static str,
+    pub test: &'static str,
+    pub threads: usize,
+    pub elapsed: Duration,
+    // acquisitions per thread
+    pub per_thread: Vec<u64>,
+}
+
+impl Row {
+    pub fn ops(&self) -> u64 {
+        self.per_thread.iter().sum()
+    }
+
+    pub fn ns_per_op(&self) -> f64 {
+        self.elapsed.as_nanos() as f64 / self.ops().max(1) as f64
+    }
+
+    pub fn ops_per_sec(&self) -> f64 {
+        self.ops() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    // standard deviation / mean of the per thread counts
+    pub fn cv(&self) -> f64 {
+        let n = self.per_thread.len() as f64;
+        let mean = self.ops() as f64 / n;
+        if mean == 0.0 {
+            return 0.0;
+        }
+        let var = self
+            .per_thread
+            .iter()
+            .map(|&c| (c as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        var.sqrt() / mean
+    }
+}
+
+type Bench = fn(&'static str, &Config) -> Vec<Row>;
+
+const LOCKS: &[(&str, Bench)] = &[
+    ("tas", bench_lock::<TasLock>),
+    ("ttas", bench_lock::<TtasLock>),
+    ("ttas-exponential", bench_lock::<TtasLock<Exponential>>),
+    (
+        "ttas-spin-then-yield",
+        bench_lock::<TtasLock<SpinThenYield>>,
+    ),
+    ("ttas-jitter", bench_lock::<TtasLock<Jitter>>),
+    ("ticket", bench_lock::<RawTicketLock>),
+    ("mcs", bench_lock::<RawMcsLock>),
+    ("clh", bench_lock::<RawClhLock>),
+    ("clh-timeout", bench_lock::<RawClhTimeoutLock>),
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    ("futex", bench_lock::<RawFutexLock>),
+];
+
+fn thread_counts(max: usize) -> Vec<usize> {
+    let mut counts: Vec<usize> = (0..).map(|i| 1 << i).take_while(|&t| t < max).collect();
+    counts.push(max);
+    counts
+}
+
+fn bench_lock<R: RawLock>(lock: &'static str, config: &Config) -> Vec<Row> {
+    let mut rows = vec![latency::<R>(lock, config)];
+    for threads in thread_counts(config.max_threads) {
+        rows.push(throughput::<R>(lock, config, threads));
+    }
+    rows
+}
+
+fn latency<R: RawLock>(lock: &'static str, config: &Config) -> Row {
+    let m = Mutex::<R, u64>::new(0);
+    let start = Instant::now();
+    for _ in 0..config.iterations {
+        m.with_lock(|v| *v += 1);
+    }
+    Row {
+        lock,
+        test: "latency",
+        threads: 1,
+        elapsed: start.elapsed(),
+        per_thread: vec![config.iterations as u64],
+    }
+}
+
+// Runs for config.duration, so the slow fair locks on too few cores still finish
+fn throughput<R: RawLock>(lock: &'static str, config: &Config, threads: usize) -> Row {
+    let m = Mutex::<R, u64>::new(0);
+    let stop = AtomicBool::new(false);
+    let start = Instant::now();
+    let per_thread = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut count = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        m.with_lock(|v| *v += 1);
+                        count += 1;
+                    }
+                    count
+                })
+            })
+            .collect();
+        thread::sleep(config.duration);
+        stop.store(true, Ordering::Relaxed);
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<u64>>()
+    });
+    Row {
+        lock,
+        test: "throughput",
+        threads,
+        elapsed: start.elapsed(),
+        per_thread,
+    }
+}
+
+pub fn csv(rows: &[Row]) -> String {
+    let mut out = String::from(
+        "lock,test,threads,ops,seconds,ns_per_op,ops_per_sec,min_per_thread,max_per_thread,cv\n",
+    );
+    for r in rows {
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{:.6},{:.2},{:.0},{},{},{:.4}",
+            r.lock,
+            r.test,
+            r.threads,
+            r.ops(),
+            r.elapsed.as_secs_f64(),
+            r.ns_per_op(),
+            r.ops_per_sec(),
+            r.per_thread.iter().min().unwrap_or(&0),
+            r.per_thread.iter().max().unwrap_or(&0),
+            r.cv()
+        );
+    }
+    out
+}
+
+// Written by hand, names and tests are plain ascii so there is nothing to escape
+pub fn json(config: &Config, rows: &[Row]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{{");
+    let _ = writeln!(
+        out,
+        "  \"cores\": {},",
+        thread::available_parallelism().map_or(1, |n| n.get())
+    );
+    let _ = writeln!(out, "  \"duration_ms\": {},", config.duration.as_millis());
+    let _ = writeln!(out, "  \"latency_iterations\": {},", config.iterations);
+    let _ = writeln!(out, "  \"results\": [");
+    for (i, r) in rows.iter().enumerate() {
+        let per_thread: Vec<String> = r.per_thread.iter().map(u64::to_string).collect();
+        let _ = writeln!(
+            out,
+            "    {{\"lock\": \"{}\", \"test\": \"{}\", \"threads\": {}, \"ops\": {}, \"seconds\": {:.6}, \"ns_per_op\": {:.2}, \"ops_per_sec\": {:.0}, \"cv\": {:.4}, \"per_thread\": [{}]}}{}",
+            r.lock,
+            r.test,
+            r.threads,
+            r.ops(),
+            r.elapsed.as_secs_f64(),
+            r.ns_per_op(),
+            r.ops_per_sec(),
+            r.cv(),
+            per_thread.join(", "),
+            if i + 1 < rows.len() { "," } else { "" }
+        );
+    }
+    let _ = writeln!(out, "  ]");
+    let _ = writeln!(out, "}}");
+    out
+}
+
+// `cargo run --release -- bench [--threads N] [--duration-ms D] [--iterations M] [--out PREFIX] [LOCK ...]`
+// with lock names only run those
+pub fn run(config: &Config, prefix: &str, only: &[String]) -> io::Result<()> {
+    for name in only {
+        if !LOCKS.iter().any(|&(lock, _)| lock == name) {
+            eprintln!("unknown lock {name}");
+        }
+    }
+    // don't leave header only files behind when every name was a typo
+    if !only.is_empty()
+        && !LOCKS
+            .iter()
+            .any(|&(lock, _)| only.iter().any(|n| n == lock))
+    {
+        let names: Vec<&str> = LOCKS.iter().map(|&(lock, _)| lock).collect();
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no such lock, pick from {}", names.join(" ")),
+        ));
+    }
+    let mut rows = Vec::new();
+    for &(name, bench) in LOCKS {
+        if !only.is_empty() && !only.iter().any(|n| n == name) {
+            continue;
+        }
+        for row in bench(name, config) {
+            match row.test {
+                "latency" => println!("{:<22} latency     {:>8.1} ns", name, row.ns_per_op()),
+                _ => println!(
+                    "{:<22} {:>2} threads {:>8.2} M ops/s  cv {:.3}",
+                    name,
+                    row.threads,
+                    row.ops_per_sec() / 1e6,
+                    row.cv()
+                ),
+            }
+            rows.push(row);
+        }
+    }
+    fs::write(format!("{prefix}.csv"), csv(&rows))?;
+    fs::write(format!("{prefix}.json"), json(config, &rows))?;
+    println!("wrote {prefix}.csv and {prefix}.json");
+    Ok(())
+}
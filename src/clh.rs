@@ -0,0 +1,273 @@
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::time::Instant;
+
+use crate::mutex::Mutex;
+use crate::raw::RawLock;
+
+// CLH queue lock ( Craig, Landin & Hagersten ).
+// Like MCS every waiter has its own node, but here it spins on the node of its
+// predecessor, so a waiter only needs to know who is in front of it ( no `next` links ).
+// tail == null means the lock is free and nobody waits.
+pub type ClhLock<T> = Mutex<RawClhLock, T>;
+
+pub struct RawClhLock {
+    tail: AtomicPtr<ClhNode>,
+    // node of the current holder, only the holder touches it so Relaxed is enough
+    holder: AtomicPtr<ClhNode>,
+}
+
+struct ClhNode {
+    // true while the owner waits for / holds the lock
+    locked: AtomicBool,
+}
+
+unsafe impl RawLock for RawClhLock {
+    fn new() -> Self {
+        Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            holder: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn lock(&self) {
+        let node = ClhNode::alloc();
+        // AcqRel : Acquire pairs with the Release CAS in unlock when the queue was empty,
+        // Release publishes our node to whoever queues behind us
+        let pred = self.tail.swap(node, Ordering::AcqRel);
+        if !pred.is_null() {
+            // Safety : only we ( its successor ) free pred, after it's released
+            unsafe {
+                // Acquire pairs with the Release in unlock
+                while (*pred).locked.load(Ordering::Acquire) {
+                    std::hint::spin_loop();
+                }
+                drop(Box::from_raw(pred));
+            }
+        }
+        self.holder.store(node, Ordering::Relaxed);
+    }
+
+    // Only succeeds if the queue is empty.
+    // Plain CLH can't leave the queue ( see RawClhTimeoutLock for that ),
+    // so timed attempts don't enqueue, they use the default polling try_lock_until
+    fn try_lock(&self) -> bool {
+        let node = ClhNode::alloc();
+        if self
+            .tail
+            .compare_exchange(ptr::null_mut(), node, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            // Safety : nobody saw the node, it's still ours
+            drop(unsafe { Box::from_raw(node) });
+            return false;
+        }
+        self.holder.store(node, Ordering::Relaxed);
+        true
+    }
+
+    unsafe fn unlock(&self) {
+        let node = self.holder.load(Ordering::Relaxed);
+        // nobody behind us, empty the queue and free our own node
+        if self
+            .tail
+            .compare_exchange(node, ptr::null_mut(), Ordering::Release, Ordering::Relaxed)
+            .is_ok()
+        {
+            drop(Box::from_raw(node));
+            return;
+        }
+        // successor spins on our node and frees it, don't touch it after this.
+        // Release pairs with its Acquire spin in lock
+        (*node).locked.store(false, Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        !self.tail.load(Ordering::Relaxed).is_null()
+    }
+}
+
+impl ClhNode {
+    fn alloc() -> *mut ClhNode {
+        Box::into_raw(Box::new(ClhNode {
+            locked: AtomicBool::new(true),
+        }))
+    }
+}
+
+// CLH-TO ( Scott & Scherer ), CLH where a waiter may give up its place in the queue.
+// Instead of a flag every node has a `pred` pointer :
+//   null      -> owner still waits or holds the lock
+//   AVAILABLE -> owner released the lock
+//   other     -> owner gave up, whoever is behind it should wait on that node instead
+// Unlike plain CLH a free lock doesn't always mean tail == null : a waiter that gives up
+// at the tail puts its predecessor back, which may have been released meanwhile. The
+// next one to queue up finds it AVAILABLE and takes over, so try_lock queues up too.
+pub type ClhTimeoutLock<T> = Mutex<RawClhTimeoutLock, T>;
+
+pub struct RawClhTimeoutLock {
+    tail: AtomicPtr<ClhToNode>,
+    // node of the current holder, null while free ( only a hint for is_locked then )
+    holder: AtomicPtr<ClhToNode>,
+}
+
+struct ClhToNode {
+    pred: AtomicPtr<ClhToNode>,
+}
+
+// never dereferenced, only compared against
+fn available() -> *mut ClhToNode {
+    ptr::dangling_mut()
+}
+
+impl RawClhTimeoutLock {
+    // false if deadline passed, our node is then handed over to the queue
+    fn acquire(&self, deadline: Option<Instant>) -> bool {
+        let node = ClhToNode::alloc();
+        let mut pred = self.tail.swap(node, Ordering::AcqRel);
+        if pred.is_null() {
+            self.holder.store(node, Ordering::Relaxed);
+            return true;
+        }
+        loop {
+            // Safety : we are pred's only successor, nobody else frees it
+            let pred_pred = unsafe { (*pred).pred.load(Ordering::Acquire) };
+            if pred_pred == available() {
+                drop(unsafe { Box::from_raw(pred) });
+                self.holder.store(node, Ordering::Relaxed);
+                return true;
+            }
+            if !pred_pred.is_null() {
+                // pred gave up, wait behind its predecessor instead
+                drop(unsafe { Box::from_raw(pred) });
+                pred = pred_pred;
+                continue;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
+            }
+            std::hint::spin_loop();
+        }
+        // give up : if we are the tail just put pred back, otherwise tell our
+        // successor to wait on pred ( it frees our node then )
+        if self
+            .tail
+            .compare_exchange(node, pred, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+        {
+            drop(unsafe { Box::from_raw(node) });
+        } else {
+            unsafe { (*node).pred.store(pred, Ordering::Release) };
+        }
+        false
+    }
+}
+
+unsafe impl RawLock for RawClhTimeoutLock {
+    fn new() -> Self {
+        Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            holder: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn lock(&self) {
+        self.acquire(None);
+    }
+
+    // Queue up with a deadline that already passed : we get the lock if it's free ( even
+    // with a released node left at the tail ) and leave right away otherwise.
+    // A CAS from null would only see the first case
+    fn try_lock(&self) -> bool {
+        self.acquire(Some(Instant::now()))
+    }
+
+    // Waits in the queue like lock(), leaves it if deadline passes first
+    fn try_lock_until(&self, deadline: Instant) -> bool {
+        self.acquire(Some(deadline))
+    }
+
+    unsafe fn unlock(&self) {
+        let node = self.holder.swap(ptr::null_mut(), Ordering::Relaxed);
+        if self
+            .tail
+            .compare_exchange(node, ptr::null_mut(), Ordering::Release, Ordering::Relaxed)
+            .is_ok()
+        {
+            drop(Box::from_raw(node));
+            return;
+        }
+        // Release pairs with the Acquire load of `pred` in our successor's acquire
+        (*node).pred.store(available(), Ordering::Release);
+    }
+
+    // tail can't tell ( see above ), so look at the holder
+    fn is_locked(&self) -> bool {
+        !self.holder.load(Ordering::Relaxed).is_null()
+    }
+}
+
+impl Drop for RawClhTimeoutLock {
+    // waiters that gave up can leave their nodes queued with nobody behind them
+    fn drop(&mut self) {
+        let mut node = *self.tail.get_mut();
+        while !node.is_null() && node != available() {
+            // Safety : no holder or waiters left, we own every node in the chain
+            let node_box = unsafe { Box::from_raw(node) };
+            node = node_box.pred.load(Ordering::Relaxed);
+        }
+    }
+}
+
+impl ClhToNode {
+    fn alloc() -> *mut ClhToNode {
+        Box::into_raw(Box::new(ClhToNode {
+            pred: AtomicPtr::new(ptr::null_mut()),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    // A waiter timing out while the holder unlocks must not leave the lock looking taken
+    #[test]
+    fn timeout_racing_unlock_leaves_lock_free() {
+        for i in 0..200 {
+            let lock = RawClhTimeoutLock::new();
+            lock.lock();
+            thread::scope(|s| {
+                s.spawn(|| {
+                    if lock.try_lock_until(Instant::now() + Duration::from_micros(i % 20)) {
+                        // Safety : we just got it
+                        unsafe { lock.unlock() };
+                    }
+                });
+                thread::sleep(Duration::from_micros(i % 23));
+                // Safety : locked above
+                unsafe { lock.unlock() };
+            });
+            assert!(!lock.is_locked(), "round {i}");
+            assert!(lock.try_lock(), "round {i}");
+            assert!(lock.is_locked());
+            assert!(!lock.try_lock());
+            unsafe { lock.unlock() };
+        }
+    }
+
+    #[test]
+    fn try_lock_takes_over_released_tail() {
+        let lock = RawClhTimeoutLock::new();
+        lock.lock();
+        // a waiter queues up behind the holder and gives up, putting the holder's node back
+        assert!(!lock.try_lock_until(Instant::now() + Duration::from_millis(1)));
+        unsafe { lock.unlock() };
+        assert!(lock.try_lock());
+        unsafe { lock.unlock() };
+        lock.lock();
+        unsafe { lock.unlock() };
+    }
+}
@@ -0,0 +1,174 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{LockResult, PoisonError};
+use std::time::Duration;
+
+use crate::futex;
+use crate::mutex::MutexGuard;
+use crate::raw::RawLock;
+
+// Wait for a condition without spinning inside with_lock : unlock the mutex, sleep,
+// lock it again when someone notifies.
+// The futex word is just a counter bumped by every notify. A waiter reads it while still
+// holding the mutex, so a notify that happens after we unlock changes it and the
+// kernel won't put us to sleep ( no lost wakeups ).
+pub struct Condvar {
+    futex: AtomicU32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            futex: AtomicU32::new(0),
+        }
+    }
+
+    // Can wake up spuriously, check the condition in a loop ( or use wait_while )
+    pub fn wait<'a, R: RawLock, T>(
+        &self,
+        guard: MutexGuard<'a, R, T>,
+    ) -> LockResult<MutexGuard<'a, R, T>> {
+        let mutex = MutexGuard::mutex(&guard);
+        // Relaxed is enough, the mutex orders this against the notifier's changes
+        let seq = self.futex.load(Ordering::Relaxed);
+        drop(guard);
+        futex::wait(&self.futex, seq, None);
+        // lock() acquires, so we see everything written before the notify
+        mutex.lock()
+    }
+
+    // Sleeps as long as condition returns true
+    pub fn wait_while<'a, R: RawLock, T>(
+        &self,
+        mut guard: MutexGuard<'a, R, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> LockResult<MutexGuard<'a, R, T>> {
+        while condition(&mut guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    pub fn wait_timeout<'a, R: RawLock, T>(
+        &self,
+        guard: MutexGuard<'a, R, T>,
+        timeout: Duration,
+    ) -> LockResult<(MutexGuard<'a, R, T>, WaitTimeoutResult)> {
+        let mutex = MutexGuard::mutex(&guard);
+        let seq = self.futex.load(Ordering::Relaxed);
+        drop(guard);
+        let result = WaitTimeoutResult(!futex::wait(&self.futex, seq, Some(timeout)));
+        match mutex.lock() {
+            Ok(guard) => Ok((guard, result)),
+            Err(poisoned) => Err(PoisonError::new((poisoned.into_inner(), result))),
+        }
+    }
+
+    pub fn notify_one(&self) {
+        self.futex.fetch_add(1, Ordering::Relaxed);
+        futex::wake(&self.futex, 1);
+    }
+
+    pub fn notify_all(&self) {
+        self.futex.fetch_add(1, Ordering::Relaxed);
+        futex::wake(&self.futex, i32::MAX);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::futex::FutexMutex;
+    use std::collections::VecDeque;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn producer_consumers_get_every_item_once() {
+        const ITEMS: u64 = 3000;
+        // queue, producer done
+        let queue = FutexMutex::new((VecDeque::new(), false));
+        let cv = Condvar::new();
+        let sums: Vec<(u64, u64)> = thread::scope(|s| {
+            let consumers: Vec<_> = (0..3)
+                .map(|_| {
+                    s.spawn(|| {
+                        let (mut count, mut sum) = (0, 0);
+                        loop {
+                            let mut guard = cv
+                                .wait_while(queue.lock().unwrap(), |(q, done)| {
+                                    q.is_empty() && !*done
+                                })
+                                .unwrap();
+                            match guard.0.pop_front() {
+                                Some(item) => {
+                                    count += 1;
+                                    sum += item;
+                                }
+                                None => return (count, sum),
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for item in 1..=ITEMS {
+                queue.lock().unwrap().0.push_back(item);
+                cv.notify_one();
+            }
+            queue.lock().unwrap().1 = true;
+            cv.notify_all();
+            consumers.into_iter().map(|c| c.join().unwrap()).collect()
+        });
+        let count: u64 = sums.iter().map(|&(c, _)| c).sum();
+        let sum: u64 = sums.iter().map(|&(_, s)| s).sum();
+        assert_eq!(count, ITEMS);
+        assert_eq!(sum, ITEMS * (ITEMS + 1) / 2);
+    }
+
+    #[test]
+    fn wait_timeout_times_out_without_notify() {
+        let m = FutexMutex::new(());
+        let cv = Condvar::new();
+        let start = Instant::now();
+        let (_guard, result) = cv
+            .wait_timeout(m.lock().unwrap(), Duration::from_millis(50))
+            .unwrap();
+        assert!(result.timed_out());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn wait_timeout_wakes_up_on_notify() {
+        let m = FutexMutex::new(false);
+        let cv = Condvar::new();
+        let start = Instant::now();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                *m.lock().unwrap() = true;
+                cv.notify_one();
+            });
+            let mut guard = m.lock().unwrap();
+            while !*guard {
+                let (g, result) = cv.wait_timeout(guard, Duration::from_secs(10)).unwrap();
+                assert!(!result.timed_out());
+                guard = g;
+            }
+        });
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
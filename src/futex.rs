@@ -0,0 +1,148 @@
+use std::ffi::c_long;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::mutex::Mutex;
+use crate::raw::RawLock;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1; // locked, nobody sleeping
+const CONTENDED: u32 = 2; // locked and maybe someone sleeping, unlock has to wake
+
+// how long we spin before parking, most critical sections are short
+pub const SPIN_LIMIT: u32 = 100;
+
+#[cfg(target_arch = "x86_64")]
+const SYS_FUTEX: c_long = 202;
+#[cfg(target_arch = "aarch64")]
+const SYS_FUTEX: c_long = 98;
+
+const FUTEX_WAIT_PRIVATE: c_long = 128;
+const FUTEX_WAKE_PRIVATE: c_long = 128 | 1;
+
+extern "C" {
+    // from libc, std links it anyway
+    fn syscall(num: c_long, ...) -> c_long;
+}
+
+#[repr(C)]
+struct Timespec {
+    tv_sec: i64,
+    tv_nsec: c_long,
+}
+
+// Sleeps while `futex` still holds `expected`. Returns false if the timeout ran out,
+// true when woken up ( or spuriously, callers always re-check the value )
+pub fn wait(futex: &AtomicU32, expected: u32, timeout: Option<Duration>) -> bool {
+    let ts = timeout.map(|d| Timespec {
+        tv_sec: d.as_secs().min(i64::MAX as u64) as i64,
+        tv_nsec: d.subsec_nanos() as c_long,
+    });
+    let ts_ptr = ts
+        .as_ref()
+        .map_or(std::ptr::null(), |ts| ts as *const Timespec);
+    // Safety : futex points to a live AtomicU32, kernel only reads it
+    let r = unsafe {
+        syscall(
+            SYS_FUTEX,
+            futex.as_ptr(),
+            FUTEX_WAIT_PRIVATE,
+            expected,
+            ts_ptr,
+        )
+    };
+    !(r < 0 && std::io::Error::last_os_error().raw_os_error() == Some(110 /* ETIMEDOUT */))
+}
+
+// Wakes up to `n` threads sleeping on `futex`
+pub fn wake(futex: &AtomicU32, n: i32) {
+    // Safety : same as in wait
+    unsafe {
+        syscall(SYS_FUTEX, futex.as_ptr(), FUTEX_WAKE_PRIVATE, n);
+    }
+}
+
+// Same idea as SpinMutex but waiters go to sleep in the kernel instead of spinning
+pub type FutexMutex<T> = Mutex<RawFutexLock, T>;
+
+pub struct RawFutexLock {
+    state: AtomicU32,
+}
+
+impl RawFutexLock {
+    // Returns false only if deadline passed before we got the lock
+    fn acquire(&self, deadline: Option<Instant>) -> bool {
+        // fast path, nobody holds it
+        if self.try_lock() {
+            return true;
+        }
+
+        let mut state = self.spin();
+        if state == UNLOCKED && self.try_lock() {
+            return true;
+        }
+
+        loop {
+            // mark it CONTENDED so the holder wakes us up, if it was free we got it
+            // ( and it stays CONTENDED since we don't know if others sleep too )
+            if state != CONTENDED && self.state.swap(CONTENDED, Ordering::Acquire) == UNLOCKED {
+                return true;
+            }
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if !left.is_zero() => Some(left),
+                    _ => return false,
+                },
+                None => None,
+            };
+            wait(&self.state, CONTENDED, timeout);
+            state = self.spin();
+        }
+    }
+
+    // Spin a bit while someone holds it without sleepers, they'll probably unlock soon
+    fn spin(&self) -> u32 {
+        let mut spins = SPIN_LIMIT;
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state != LOCKED || spins == 0 {
+                return state;
+            }
+            std::hint::spin_loop();
+            spins -= 1;
+        }
+    }
+}
+
+unsafe impl RawLock for RawFutexLock {
+    fn new() -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+        }
+    }
+
+    fn lock(&self) {
+        self.acquire(None);
+    }
+
+    fn try_lock(&self) -> bool {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn try_lock_until(&self, deadline: Instant) -> bool {
+        self.acquire(Some(deadline))
+    }
+
+    unsafe fn unlock(&self) {
+        // Release so the next owner sees our writes
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            wake(&self.state, 1);
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != UNLOCKED
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex as StdMutex;
+use std::thread;
+use std::time::Instant;
+
+// Classic litmus tests on real threads : a few threads poke at x and y, we record what
+// they read and count how often each outcome happens for every ordering combination.
+// The "weak" outcome is the one sequential consistency forbids, seeing it means the
+// hardware ( or compiler ) reordered something.
+//
+// Threads stay alive for the whole test and run BATCH fresh copies of x / y per round
+// between two barriers, so most of the time goes into the test and not into spawning.
+
+const BATCH: usize = 1000;
+
+// ( store ordering, load ordering )
+pub type Orders = (Ordering, Ordering);
+
+// what one thread read, unused registers stay 0
+type Regs = [usize; 2];
+
+pub struct Litmus {
+    pub name: &'static str,
+    pub about: &'static str,
+    // false if only stores take an ordering ( 2+2W )
+    loads: bool,
+    threads: &'static [fn(&[AtomicUsize; 2], Orders) -> Regs],
+    // registers of every thread + final x / y -> outcome
+    outcome: fn(&[Regs], [usize; 2]) -> Outcome,
+    weak: Outcome,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Outcome(&'static [&'static str], [usize; 4]);
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, name) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{name}={}", self.1[i])?;
+        }
+        Ok(())
+    }
+}
+
+const R2: &[&str] = &["r1", "r2"];
+const R4: &[&str] = &["r1", "r2", "r3", "r4"];
+const XY: &[&str] = &["x", "y"];
+
+pub const TESTS: &[Litmus] = &[
+    Litmus {
+        name: "MP",
+        about: "message passing : x = 1; y = 1 || r1 = y; r2 = x",
+        loads: true,
+        threads: &[
+            |l, (st, _)| {
+                l[0].store(1, Ordering::Relaxed);
+                l[1].store(1, st);
+                [0; 2]
+            },
+            |l, (_, ld)| {
+                let r1 = l[1].load(ld);
+                let r2 = l[0].load(Ordering::Relaxed);
+                [r1, r2]
+            },
+        ],
+        outcome: |r, _| Outcome(R2, [r[1][0], r[1][1], 0, 0]),
+        // saw the flag but not the data
+        weak: Outcome(R2, [1, 0, 0, 0]),
+    },
+    Litmus {
+        name: "SB",
+        about: "store buffering : x = 1; r1 = y || y = 1; r2 = x",
+        loads: true,
+        threads: &[
+            |l, (st, ld)| {
+                l[0].store(1, st);
+                [l[1].load(ld), 0]
+            },
+            |l, (st, ld)| {
+                l[1].store(1, st);
+                [l[0].load(ld), 0]
+            },
+        ],
+        outcome: |r, _| Outcome(R2, [r[0][0], r[1][0], 0, 0]),
+        // both loads ran before either store left the store buffer
+        weak: Outcome(R2, [0, 0, 0, 0]),
+    },
+    Litmus {
+        name: "LB",
+        about: "load buffering : r1 = x; y = 1 || r2 = y; x = 1",
+        loads: true,
+        threads: &[
+            |l, (st, ld)| {
+                let r1 = l[0].load(ld);
+                l[1].store(1, st);
+                [r1, 0]
+            },
+            |l, (st, ld)| {
+                let r2 = l[1].load(ld);
+                l[0].store(1, st);
+                [r2, 0]
+            },
+        ],
+        outcome: |r, _| Outcome(R2, [r[0][0], r[1][0], 0, 0]),
+        // each load saw the store that comes after the other load
+        weak: Outcome(R2, [1, 1, 0, 0]),
+    },
+    Litmus {
+        name: "IRIW",
+        about: "independent reads of independent writes : x = 1 || y = 1 || r1 = x; r2 = y || r3 = y; r4 = x",
+        loads: true,
+        threads: &[
+            |l, (st, _)| {
+                l[0].store(1, st);
+                [0; 2]
+            },
+            |l, (st, _)| {
+                l[1].store(1, st);
+                [0; 2]
+            },
+            |l, (_, ld)| [l[0].load(ld), l[1].load(ld)],
+            |l, (_, ld)| [l[1].load(ld), l[0].load(ld)],
+        ],
+        outcome: |r, _| Outcome(R4, [r[2][0], r[2][1], r[3][0], r[3][1]]),
+        // the two readers disagree on which store happened first
+        weak: Outcome(R4, [1, 0, 1, 0]),
+    },
+    Litmus {
+        name: "2+2W",
+        about: "two plus two writes : x = 1; y = 2 || y = 1; x = 2",
+        loads: false,
+        threads: &[
+            |l, (st, _)| {
+                l[0].store(1, st);
+                l[1].store(2, st);
+                [0; 2]
+            },
+            |l, (st, _)| {
+                l[1].store(1, st);
+                l[0].store(2, st);
+                [0; 2]
+            },
+        ],
+        outcome: |_, m| Outcome(XY, [m[0], m[1], 0, 0]),
+        // each thread's first store ended up last
+        weak: Outcome(XY, [1, 1, 0, 0]),
+    },
+];
+
+const STORES: [Ordering; 3] = [Ordering::Relaxed, Ordering::Release, Ordering::SeqCst];
+const LOADS: [Ordering; 3] = [Ordering::Relaxed, Ordering::Acquire, Ordering::SeqCst];
+
+impl Litmus {
+    pub fn orders(&self) -> Vec<Orders> {
+        let loads: &[Ordering] = if self.loads {
+            &LOADS
+        } else {
+            &[Ordering::Relaxed]
+        };
+        STORES
+            .iter()
+            .flat_map(|&st| loads.iter().map(move |&ld| (st, ld)))
+            .collect()
+    }
+
+    // Outcome -> how often, over `iterations` runs
+    pub fn run(&self, orders: Orders, iterations: usize) -> BTreeMap<Outcome, usize> {
+        let n = self.threads.len();
+        let cells: Vec<[AtomicUsize; 2]> = (0..BATCH).map(|_| Default::default()).collect();
+        let regs: Vec<StdMutex<Vec<Regs>>> =
+            (0..n).map(|_| StdMutex::new(vec![[0; 2]; BATCH])).collect();
+        let barrier = SpinBarrier::new(n + 1);
+        let stop = AtomicBool::new(false);
+        let mut counts = BTreeMap::new();
+
+        thread::scope(|s| {
+            for (t, body) in self.threads.iter().enumerate() {
+                let (cells, regs, barrier, stop) = (&cells, &regs[t], &barrier, &stop);
+                s.spawn(move || {
+                    let mut out = vec![[0; 2]; BATCH];
+                    loop {
+                        barrier.wait(); // <- round starts
+                        if stop.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        for (cell, r) in cells.iter().zip(out.iter_mut()) {
+                            *r = body(cell, orders);
+                        }
+                        regs.lock().unwrap().copy_from_slice(&out);
+                        barrier.wait(); // <- round done
+                    }
+                });
+            }
+
+            let mut done = 0;
+            while done < iterations {
+                barrier.wait();
+                barrier.wait();
+                let regs: Vec<_> = regs.iter().map(|r| r.lock().unwrap()).collect();
+                let round = BATCH.min(iterations - done);
+                for (i, cell) in cells.iter().enumerate().take(round) {
+                    let per_thread: Vec<Regs> = regs.iter().map(|r| r[i]).collect();
+                    let mem = [
+                        cell[0].load(Ordering::Relaxed),
+                        cell[1].load(Ordering::Relaxed),
+                    ];
+                    *counts.entry((self.outcome)(&per_thread, mem)).or_insert(0) += 1;
+                }
+                for cell in &cells {
+                    cell[0].store(0, Ordering::Relaxed);
+                    cell[1].store(0, Ordering::Relaxed);
+                }
+                done += round;
+            }
+            stop.store(true, Ordering::Relaxed);
+            barrier.wait();
+        });
+        counts
+    }
+}
+
+// All parties wait until the last one arrives. Spins first to start everybody at the same
+// time, then yields so it doesn't starve the others when there are fewer cores than threads.
+struct SpinBarrier {
+    parties: usize,
+    arrived: AtomicUsize,
+    generation: AtomicUsize,
+}
+
+impl SpinBarrier {
+    fn new(parties: usize) -> Self {
+        Self {
+            parties,
+            arrived: AtomicUsize::new(0),
+            generation: AtomicUsize::new(0),
+        }
+    }
+
+    fn wait(&self) {
+        let generation = self.generation.load(Ordering::Acquire);
+        // AcqRel : the last one to arrive sees everything the others did before the barrier
+        if self.arrived.fetch_add(1, Ordering::AcqRel) + 1 == self.parties {
+            self.arrived.store(0, Ordering::Relaxed);
+            // Release pairs with the Acquire loads of the waiters below
+            self.generation.fetch_add(1, Ordering::Release);
+            return;
+        }
+        let mut spins = 0;
+        while self.generation.load(Ordering::Acquire) == generation {
+            if spins < 1000 {
+                spins += 1;
+                std::hint::spin_loop();
+            } else {
+                thread::yield_now();
+            }
+        }
+    }
+}
+
+// `cargo run --release -- litmus [--iterations N] [MP SB ...]`
+pub fn run(iterations: usize, names: &[String]) {
+    for name in names {
+        if !TESTS.iter().any(|t| t.name.eq_ignore_ascii_case(name)) {
+            eprintln!("unknown litmus test {name}");
+        }
+    }
+    for test in TESTS {
+        if !names.is_empty() && !names.iter().any(|n| n.eq_ignore_ascii_case(test.name)) {
+            continue;
+        }
+        println!("{} : {}", test.name, test.about);
+        println!(
+            "  weak outcome ( forbidden under sequential consistency ) : {}",
+            test.weak
+        );
+        for orders in test.orders() {
+            let start = Instant::now();
+            let counts = test.run(orders, iterations);
+            let label = if test.loads {
+                format!("{:?} / {:?}", orders.0, orders.1)
+            } else {
+                format!("{:?}", orders.0)
+            };
+            let mut line = format!("  {label:<18}");
+            for (outcome, count) in &counts {
+                line += &format!("  {outcome} : {count}");
+                if *outcome == test.weak {
+                    line += " *";
+                }
+            }
+            if !counts.contains_key(&test.weak) {
+                line += &format!("  {} : 0", test.weak);
+            }
+            println!("{line}  ( {:.1?} )", start.elapsed());
+        }
+        println!();
+    }
+}
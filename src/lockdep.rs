@@ -0,0 +1,217 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::panic::Location;
+use std::sync::Mutex as StdMutex;
+
+// Lock dependency checker, same idea as lockdep in the linux kernel.
+// Every Mutex gets a class ( the place where Mutex::new was called, so all mutexes
+// created by the same line share one ). Taking B while holding A adds the edge A -> B
+// to a global graph. The first time an edge closes a cycle two threads could deadlock,
+// even if this particular run got lucky, so we print a report right away.
+//
+// Uses the std Mutex on purpose, it must not depend on the locks it checks.
+
+type Site = &'static Location<'static>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Class(usize);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Edge {
+    pub from: Site, // class of the lock we held
+    pub to: Site,   // class of the lock we took
+    pub held_at: Site,
+    pub acquired_at: Site,
+}
+
+// One potential deadlock : `edge` was just taken, `cycle` is the path that was
+// already in the graph going back from edge.to to edge.from
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub edge: Edge,
+    pub cycle: Vec<Edge>,
+}
+
+struct Graph {
+    classes: Vec<Site>,
+    by_site: BTreeMap<Site, usize>,
+    // first time we saw each dependency, keeps the sites for reports
+    edges: BTreeMap<(usize, usize), Edge>,
+    reports: Vec<Report>,
+}
+
+static GRAPH: StdMutex<Graph> = StdMutex::new(Graph {
+    classes: Vec::new(),
+    by_site: BTreeMap::new(),
+    edges: BTreeMap::new(),
+    reports: Vec::new(),
+});
+
+thread_local! {
+    // locks this thread holds right now, with where it took them
+    static HELD: RefCell<Vec<(Class, Site)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn graph() -> std::sync::MutexGuard<'static, Graph> {
+    // a panic inside lockdep shouldn't turn every later lock into another panic
+    GRAPH.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn register(site: Site) -> Class {
+    let mut graph = graph();
+    if let Some(&id) = graph.by_site.get(site) {
+        return Class(id);
+    }
+    let id = graph.classes.len();
+    graph.classes.push(site);
+    graph.by_site.insert(site, id);
+    Class(id)
+}
+
+// Before a blocking lock : record "took `class` while holding X" for everything we hold
+pub fn acquire(class: Class, site: Site) {
+    HELD.with(|held| {
+        let held = held.borrow();
+        if held.is_empty() {
+            return;
+        }
+        let mut graph = graph();
+        for &(held_class, held_at) in held.iter() {
+            // two locks of one class taken in a row ( e.g. a Vec of mutexes ) would report
+            // every time, the order between them is the caller's business
+            if held_class == class || graph.edges.contains_key(&(held_class.0, class.0)) {
+                continue;
+            }
+            let edge = Edge {
+                from: graph.classes[held_class.0],
+                to: graph.classes[class.0],
+                held_at,
+                acquired_at: site,
+            };
+            graph.edges.insert((held_class.0, class.0), edge);
+            if let Some(cycle) = graph.path(class.0, held_class.0) {
+                let report = Report { edge, cycle };
+                eprintln!("{report}");
+                graph.reports.push(report);
+            }
+        }
+    });
+}
+
+// After we got the lock ( also for try_lock, it can't deadlock itself but what
+// we take while holding it can )
+pub fn acquired(class: Class, site: Site) {
+    HELD.with(|held| held.borrow_mut().push((class, site)));
+}
+
+pub fn release(class: Class) {
+    HELD.with(|held| {
+        let mut held = held.borrow_mut();
+        // locks don't have to be released in order
+        if let Some(i) = held.iter().rposition(|&(c, _)| c == class) {
+            held.remove(i);
+        }
+    });
+}
+
+pub fn reports() -> Vec<Report> {
+    graph().reports.clone()
+}
+
+impl Graph {
+    // DFS, edges along some path from -> .. -> to
+    fn path(&self, from: usize, to: usize) -> Option<Vec<Edge>> {
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![(from, Vec::new())];
+        while let Some((class, path)) = stack.pop() {
+            if class == to {
+                return Some(path);
+            }
+            if !seen.insert(class) {
+                continue;
+            }
+            for (&(_, next), edge) in self.edges.range((class, 0)..=(class, usize::MAX)) {
+                let mut path = path.clone();
+                path.push(*edge);
+                stack.push((next, path));
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "lockdep: possible deadlock")?;
+        writeln!(
+            f,
+            "  this thread took lock of class {} at {}",
+            self.edge.to, self.edge.acquired_at
+        )?;
+        writeln!(
+            f,
+            "  while holding lock of class {} taken at {}",
+            self.edge.from, self.edge.held_at
+        )?;
+        writeln!(f, "  but the opposite order was seen before :")?;
+        for edge in &self.cycle {
+            writeln!(
+                f,
+                "    took class {} at {} while holding class {} taken at {}",
+                edge.to, edge.acquired_at, edge.from, edge.held_at
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multi::lock_all;
+    use crate::mutex::SpinMutex;
+
+    // reports for classes created in this file, other tests may add their own
+    fn reports_here(about: &[Site]) -> usize {
+        reports()
+            .iter()
+            .filter(|r| about.contains(&r.edge.from) && about.contains(&r.edge.to))
+            .count()
+    }
+
+    // Mutex::new is #[track_caller] too, so its class is the caller's line as well
+    #[track_caller]
+    fn mutex() -> (SpinMutex<u32>, Site) {
+        (SpinMutex::new(0), Location::caller())
+    }
+
+    #[test]
+    fn opposite_order_reports_once() {
+        let (a, site_a) = mutex();
+        let (b, site_b) = mutex();
+        let sites = [site_a, site_b];
+        {
+            let _a = a.lock().unwrap();
+            let _b = b.lock().unwrap();
+        }
+        assert_eq!(reports_here(&sites), 0);
+        for _ in 0..2 {
+            let _b = b.lock().unwrap();
+            let _a = a.lock().unwrap();
+        }
+        assert_eq!(reports_here(&sites), 1);
+    }
+
+    #[test]
+    fn lock_all_in_both_orders_reports_nothing() {
+        let (c, site_c) = mutex();
+        let (d, site_d) = mutex();
+        let sites = [site_c, site_d];
+        for _ in 0..2 {
+            drop(lock_all((&c, &d)).unwrap());
+            drop(lock_all((&d, &c)).unwrap());
+        }
+        assert_eq!(reports_here(&sites), 0);
+    }
+}
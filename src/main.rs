@@ -1,84 +1,203 @@
 #![allow(dead_code)]
-use std::cell::UnsafeCell;
-use std::sync::atomic::{AtomicBool, Ordering};
+mod backoff;
+mod bench;
+mod clh;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod condvar;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod futex;
+mod litmus;
+#[cfg(feature = "lockdep")]
+mod lockdep;
+mod mcs;
+mod model;
+mod multi;
+mod mutex;
+mod poison;
+mod raw;
+mod reentrant;
+mod rwlock;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod semaphore;
+mod seqlock;
+#[cfg(feature = "stats")]
+mod stats;
+mod stress;
+mod thread_id;
+mod ticket;
+#[cfg(feature = "trace")]
+mod trace;
+mod weak;
 
-const LOCKED: bool = true;
-const UNLOCKED: bool = false;
+const USAGE: &str = "usage : atomics <command> [options]
 
-struct Mutex<T> {
-    locked: AtomicBool,
-    v: UnsafeCell<T>,
-}
+commands :
+  litmus [--iterations N] [TEST ...]   run litmus tests ( MP SB LB IRIW 2+2W ) for every ordering
+  stress [--threads N] [--iterations M] count lost updates of with_lock_1 / 2 / 3
+  bench [--threads N] [--duration-ms D] [--iterations M] [--out PREFIX] [LOCK ...]
+                                        latency, throughput and fairness of every lock ( or
+                                        the ones named ), written to PREFIX.csv / PREFIX.json
+                                        ( default bench )
+  model                                 try every interleaving of with_lock_1 / 2 / 3
+  weak                                  same on simulated weak memory
+
+built with --features trace, stress and bench also take --trace FILE : every lock event
+as Chrome trace JSON ( open it in ui.perfetto.dev )
 
-// we know that Mutex is Sync
-unsafe impl<T> Sync for Mutex<T> where T: Send {}
+use --release for litmus, stress and bench, debug builds are too slow to mean much";
 
-impl<T> Mutex<T> {
-    pub fn new(t: T) -> Self {
-        Self {
-            locked: AtomicBool::new(UNLOCKED),
-            v: UnsafeCell::new(t),
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((command, rest)) = args.split_first() else {
+        println!("{USAGE}");
+        return;
+    };
+    // options each command takes, and if it takes plain arguments too
+    let (mut allowed, takes_names): (Vec<&str>, bool) = match command.as_str() {
+        "litmus" => (vec!["iterations"], true),
+        "stress" => (vec!["threads", "iterations"], false),
+        "bench" => (vec!["threads", "duration-ms", "iterations", "out"], true),
+        "model" | "weak" => (vec![], false),
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            return;
         }
+        _ => {
+            eprintln!("unknown command {command}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    if cfg!(feature = "trace") && matches!(command.as_str(), "stress" | "bench") {
+        allowed.push("trace");
     }
-    // We want to grab a lock and execute f
-    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
-        while self.locked.load(Ordering::Relaxed) != UNLOCKED { /* spin lock*/ }
-        // bug : maybe another thread runs here so it's possible for data race
-        self.locked.store(LOCKED, Ordering::Relaxed);
-        // Safety : we hold the lock so we can create mutable ref
-        let ret = f(unsafe { &mut *self.v.get() });
-        self.locked.store(UNLOCKED, Ordering::Relaxed);
-        ret
+    if rest.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{USAGE}");
+        return;
     }
-    // better implementation ( it still fails because of orderings )
-    pub fn with_lock_2<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
-        while self
-            .locked
-            .compare_exchange_weak(
-                // very inefficient but works ( all threads will fight to get that value )
-                UNLOCKED,
-                LOCKED,
-                Ordering::Relaxed,
-                Ordering::Relaxed,
-            )
-            .is_err()
-        {
-            // spin lock
-            // MESI protocol
-            // more efficient waiting if we fail with compare_exchange_weak
-            while self.locked.load(Ordering::Relaxed) == LOCKED {}
+    let (options, names) = match parse_options(rest, &allowed) {
+        Ok((_, names)) if !takes_names && !names.is_empty() => {
+            eprintln!("{command} : unexpected argument {}\n\n{USAGE}", names[0]);
+            std::process::exit(2);
+        }
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{command} : {e}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    let option = |name: &str, default: usize| options.number(name).unwrap_or(default);
+    match command.as_str() {
+        "litmus" => litmus::run(option("iterations", 1_000_000), &names),
+        "stress" => {
+            stress::run(
+                option("threads", threads()),
+                option("iterations", 1_000_000),
+            );
+            after_run(&options);
         }
-        // Safety : we hold the lock so we can create mutable ref
-        let ret = f(unsafe { &mut *self.v.get() });
-        self.locked.store(UNLOCKED, Ordering::Relaxed);
-        ret
+        "bench" => {
+            let config = bench::Config {
+                max_threads: option("threads", threads()).max(1),
+                duration: std::time::Duration::from_millis(option("duration-ms", 200) as u64),
+                iterations: option("iterations", 1_000_000),
+            };
+            let prefix = options.text("out").unwrap_or("bench");
+            if let Err(e) = bench::run(&config, prefix, &names) {
+                eprintln!("bench : {e}");
+                std::process::exit(1);
+            }
+            after_run(&options);
+        }
+        "model" => model::run(),
+        "weak" => weak::run(),
+        _ => unreachable!("checked above"),
     }
+}
 
-    // Prevent reordering of operations with Orderings ( correct impl )
-    pub fn with_lock_3<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
-        while self
-            .locked
-            .compare_exchange_weak(
-                // very inefficient but works ( all threads will fight to get that value )
-                UNLOCKED,
-                LOCKED,
-                Ordering::Acquire, // <- We acquire here
-                Ordering::Relaxed, // <- We don't care in case of failure to acquire the lock
-            )
-            .is_err()
-        {
-            // spin lock
-            // MESI protocol
-            // more efficient waiting if we fail with compare_exchange
-            while self.locked.load(Ordering::Relaxed) == LOCKED {}
+// one per core, at least two so there is something to race
+fn threads() -> usize {
+    std::thread::available_parallelism().map_or(2, |n| n.get().max(2))
+}
+
+// What the optional features collected during stress / bench
+#[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+fn after_run(options: &Options) {
+    #[cfg(feature = "stats")]
+    for stats in stats::registry() {
+        print!("{stats}");
+    }
+    #[cfg(feature = "trace")]
+    if let Some(path) = options.text("trace") {
+        if let Err(e) = trace::save(path) {
+            eprintln!("trace : {e}");
+            std::process::exit(1);
         }
-        // Safety : we hold the lock so we can create mutable ref
-        let ret = f(unsafe { &mut *self.v.get() });
-        self.locked.store(UNLOCKED, Ordering::Release); // <- Release here
-        ret
+        println!("wrote {path} ( {} events dropped )", trace::dropped());
     }
 }
 
-fn main() {
-    println!("Hello, world!");
+// options that take a file name instead of a number
+const TEXT_OPTIONS: &[&str] = &["trace", "out"];
+
+// `--name N` and `--name TEXT` pairs
+#[derive(Default)]
+struct Options {
+    numbers: Vec<(String, usize)>,
+    text: Vec<(String, String)>,
+}
+
+impl Options {
+    fn number(&self, name: &str) -> Option<usize> {
+        self.numbers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|&(_, v)| v)
+    }
+
+    fn text(&self, name: &str) -> Option<&str> {
+        self.text
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// options and everything else as plain arguments, only the `allowed` option names
+// ( a typo like --thread must not silently run with the default )
+fn parse_options(args: &[String], allowed: &[&str]) -> Result<(Options, Vec<String>), String> {
+    let mut options = Options::default();
+    let mut plain = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--") {
+            Some(name) => {
+                if !allowed.contains(&name) {
+                    return Err(format!("unknown option --{name}"));
+                }
+                if TEXT_OPTIONS.contains(&name) {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("--{name} needs a value"))?;
+                    options.text.push((name.to_owned(), value.clone()));
+                    continue;
+                }
+                let value = args
+                    .next()
+                    .and_then(|v| v.replace('_', "").parse().ok())
+                    .ok_or_else(|| format!("--{name} needs a number"))?;
+                options.numbers.push((name.to_owned(), value));
+            }
+            None => plain.push(arg.clone()),
+        }
+    }
+    Ok((options, plain))
 }
@@ -0,0 +1,727 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Condvar, Mutex as StdMutex, MutexGuard as StdMutexGuard};
+use std::thread;
+
+use crate::weak::{Memory, View};
+
+// Tiny model checker : runs a few closures on real threads but only lets one of them
+// move at a time. Before every access to a model Atomic the running thread hands the
+// baton to whoever the schedule says, so one run = one interleaving. After each run we
+// backtrack to the last choice that had another option ( depth first ) until every
+// interleaving has been tried or one of them breaks.
+//
+// Spinning would make that infinite, so spin_loop() parks the thread until some other
+// thread writes to memory ( re-reading before that would see the same values anyway ).
+//
+// By default memory is sequentially consistent : every load sees the newest store and
+// orderings are only printed in the trace. Model { weak: true, .. } switches to the weak
+// memory of weak.rs, then loads may also return older stores and orderings matter.
+
+const LOCKED: bool = true;
+const UNLOCKED: bool = false;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Runnable,
+    Spinning,
+    Done,
+}
+
+// One decision ( which thread runs next, which store a load reads ), only recorded
+// when there was more than one option
+struct Choice {
+    options: usize,
+    picked: usize,
+}
+
+struct State {
+    // the thread holding the baton
+    active: Option<usize>,
+    status: Vec<Status>,
+    // what to pick at the first choices ( replay ), after that always option 0
+    prefix: Vec<usize>,
+    trail: Vec<Choice>,
+    trace: Vec<String>,
+    // threads between enter() and the drop of the Inside guard
+    inside: Vec<usize>,
+    failure: Option<String>,
+    steps: usize,
+    finished: usize,
+    // None for sequentially consistent runs
+    memory: Option<Memory>,
+}
+
+struct Exec {
+    state: StdMutex<State>,
+    cv: Condvar,
+    max_steps: usize,
+}
+
+// unwinding payload that stops the other threads once a run failed
+struct Abort;
+
+thread_local! {
+    static CURRENT: RefCell<Option<(Arc<Exec>, usize)>> = const { RefCell::new(None) };
+}
+
+fn current() -> Option<(Arc<Exec>, usize)> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+impl Exec {
+    fn lock(&self) -> StdMutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn pick(state: &mut State) -> Option<usize> {
+        let runnable: Vec<usize> = (0..state.status.len())
+            .filter(|&t| state.status[t] == Status::Runnable)
+            .collect();
+        match runnable.len() {
+            0 => None,
+            n => Some(runnable[choose(state, n)]),
+        }
+    }
+
+    // Give the baton to whoever is next ( maybe us again ) and wait until it comes back
+    fn switch<'a>(
+        &'a self,
+        mut state: StdMutexGuard<'a, State>,
+        me: usize,
+    ) -> StdMutexGuard<'a, State> {
+        state.steps += 1;
+        if state.steps > self.max_steps {
+            let msg = format!("gave up after {} steps", self.max_steps);
+            self.fail(state, msg);
+        }
+        match Self::pick(&mut state) {
+            Some(next) => state.active = Some(next),
+            None => self.fail(state, "deadlock : every thread is spinning".to_owned()),
+        }
+        self.cv.notify_all();
+        self.wait_turn(state, me)
+    }
+
+    fn wait_turn<'a>(
+        &'a self,
+        mut state: StdMutexGuard<'a, State>,
+        me: usize,
+    ) -> StdMutexGuard<'a, State> {
+        loop {
+            if state.failure.is_some() {
+                drop(state);
+                panic::resume_unwind(Box::new(Abort));
+            }
+            if state.active == Some(me) {
+                return state;
+            }
+            state = self.cv.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    // Ends the run : first failure wins, every thread unwinds at its next step
+    fn fail(&self, mut state: StdMutexGuard<'_, State>, msg: String) -> ! {
+        state.failure.get_or_insert(msg);
+        self.cv.notify_all();
+        drop(state);
+        panic::resume_unwind(Box::new(Abort));
+    }
+}
+
+fn choose(state: &mut State, options: usize) -> usize {
+    if options < 2 {
+        return 0;
+    }
+    let picked = state.prefix.get(state.trail.len()).copied().unwrap_or(0);
+    assert!(
+        picked < options,
+        "model : replay took a different path, is the program deterministic ?"
+    );
+    state.trail.push(Choice { options, picked });
+    picked
+}
+
+// One instrumented access : schedule, do it, log it. Outside of a model run it just does it.
+// op gets the run state and our thread id ( if any ) and returns whether it wrote,
+// only writes wake up spinning threads.
+fn step<R>(
+    op: impl FnOnce(Option<(&mut State, usize)>) -> (R, bool),
+    describe: impl FnOnce(&R) -> String,
+) -> R {
+    let Some((exec, me)) = current() else {
+        return op(None).0;
+    };
+    let mut state = exec.switch(exec.lock(), me);
+    let (r, writes) = op(Some((&mut state, me)));
+    let line = format!("t{me}: {}", describe(&r));
+    state.trace.push(line);
+    if writes {
+        for s in state.status.iter_mut() {
+            if *s == Status::Spinning {
+                *s = Status::Runnable;
+            }
+        }
+    }
+    r
+}
+
+// Stand in for std::hint::spin_loop() inside model runs
+pub fn spin_loop() {
+    let Some((exec, me)) = current() else {
+        std::hint::spin_loop();
+        return;
+    };
+    let mut state = exec.lock();
+    // with weak memory there may be newer stores we just didn't read yet
+    if let Some(memory) = &mut state.memory {
+        if memory.catch_up(me) {
+            state
+                .trace
+                .push(format!("t{me}: spins, catches up with the newest stores"));
+            drop(exec.switch(state, me));
+            return;
+        }
+    }
+    state.status[me] = Status::Spinning;
+    state
+        .trace
+        .push(format!("t{me}: spins until someone writes"));
+    drop(exec.switch(state, me));
+}
+
+// Stand in for std::sync::atomic::fence(), only does something with weak memory
+pub fn fence(order: Ordering) {
+    step(
+        |ctx| {
+            if let Some((
+                State {
+                    memory: Some(memory),
+                    ..
+                },
+                me,
+            )) = ctx
+            {
+                memory.fence(me, order);
+            }
+            ((), false)
+        },
+        |_| format!("fence({order:?})"),
+    )
+}
+
+// Marks the critical section, two threads inside at once fails the run
+pub fn enter() -> Inside {
+    if let Some((exec, me)) = current() {
+        let mut state = exec.lock();
+        state
+            .trace
+            .push(format!("t{me}: enters the critical section"));
+        state.inside.push(me);
+        if let [first, .., last] = state.inside[..] {
+            let msg =
+                format!("t{first} and t{last} are inside the critical section at the same time");
+            exec.fail(state, msg);
+        }
+    }
+    Inside { _private: () }
+}
+
+pub struct Inside {
+    _private: (),
+}
+
+impl Drop for Inside {
+    fn drop(&mut self) {
+        if let Some((exec, me)) = current() {
+            let mut state = exec.lock();
+            state.inside.retain(|&t| t != me);
+            if !thread::panicking() {
+                state
+                    .trace
+                    .push(format!("t{me}: leaves the critical section"));
+            }
+        }
+    }
+}
+
+// Fails the run with msg if cond doesn't hold
+pub fn check(cond: bool, msg: &str) {
+    if cond {
+        return;
+    }
+    match current() {
+        Some((exec, me)) => {
+            let state = exec.lock();
+            exec.fail(state, format!("t{me}: {msg}"));
+        }
+        None => panic!("{msg}"),
+    }
+}
+
+// Shared memory the model threads can race on, every access is a scheduling point.
+// Keeps every store with the view it carries ( see weak.rs ), sequentially consistent
+// runs just always read the last one. Behind a std Mutex, that's fine : only one thread
+// runs at a time anyway.
+pub struct Atomic<T> {
+    name: &'static str,
+    stores: StdMutex<Vec<(T, View)>>,
+}
+
+pub type AtomicBool = Atomic<bool>;
+pub type AtomicUsize = Atomic<usize>;
+
+impl<T: Copy + PartialEq + fmt::Debug> Atomic<T> {
+    pub fn new(name: &'static str, v: T) -> Self {
+        Self {
+            name,
+            stores: StdMutex::new(vec![(v, View::default())]),
+        }
+    }
+
+    fn stores(&self) -> StdMutexGuard<'_, Vec<(T, View)>> {
+        self.stores.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn loc(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    // Plain load : with weak memory any store this thread may still see, tried newest first
+    // so the first run looks sequentially consistent. Also returns the newest if that
+    // wasn't the one read.
+    fn read(&self, ctx: Option<(&mut State, usize)>, order: Ordering) -> (T, Option<T>) {
+        let stores = self.stores();
+        let newest = stores.len() - 1;
+        let Some((state, me)) = ctx else {
+            return (stores[newest].0, None);
+        };
+        let Some(memory) = &mut state.memory else {
+            return (stores[newest].0, None);
+        };
+        let from = memory.visible_from(me, self.loc(), order);
+        let ts = newest - choose(state, newest + 1 - from);
+        if let Some(memory) = &mut state.memory {
+            memory.load(me, self.loc(), ts, &stores[ts].1, order);
+        }
+        (stores[ts].0, (ts != newest).then(|| stores[newest].0))
+    }
+
+    fn write(&self, ctx: Option<(&mut State, usize)>, v: T, order: Ordering) {
+        let mut stores = self.stores();
+        let ts = stores.len();
+        let view = match ctx {
+            Some((
+                State {
+                    memory: Some(memory),
+                    ..
+                },
+                me,
+            )) => memory.store(me, self.loc(), ts, order),
+            _ => View::default(),
+        };
+        stores.push((v, view));
+    }
+
+    // Read the newest store and, if f says so, put a new one right after it.
+    // Returns the old value and whether we wrote.
+    fn rmw(
+        &self,
+        ctx: Option<(&mut State, usize)>,
+        success: Ordering,
+        failure: Ordering,
+        f: impl FnOnce(T) -> Option<T>,
+    ) -> (T, bool) {
+        let mut stores = self.stores();
+        let ts = stores.len();
+        let (old, read) = stores[ts - 1].clone();
+        let new = f(old);
+        let memory = match ctx {
+            Some((
+                State {
+                    memory: Some(memory),
+                    ..
+                },
+                me,
+            )) => Some((memory, me)),
+            _ => None,
+        };
+        match (new, memory) {
+            (Some(new), Some((memory, me))) => {
+                let view = memory.rmw(me, self.loc(), ts, &read, success);
+                stores.push((new, view));
+            }
+            (Some(new), None) => stores.push((new, View::default())),
+            (None, Some((memory, me))) => memory.load(me, self.loc(), ts - 1, &read, failure),
+            (None, None) => {}
+        }
+        (old, new.is_some())
+    }
+
+    pub fn load(&self, order: Ordering) -> T {
+        step(
+            |ctx| (self.read(ctx, order), false),
+            |(v, newest)| match newest {
+                None => format!("{}.load({order:?}) -> {v:?}", self.name),
+                Some(newest) => format!(
+                    "{}.load({order:?}) -> {v:?} ( stale, newest is {newest:?} )",
+                    self.name
+                ),
+            },
+        )
+        .0
+    }
+
+    pub fn store(&self, v: T, order: Ordering) {
+        step(
+            |ctx| (self.write(ctx, v, order), true),
+            |_| format!("{}.store({v:?}, {order:?})", self.name),
+        )
+    }
+
+    pub fn swap(&self, v: T, order: Ordering) -> T {
+        step(
+            |ctx| self.rmw(ctx, order, order, |_| Some(v)),
+            |old| format!("{}.swap({v:?}, {order:?}) -> {old:?}", self.name),
+        )
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        step(
+            |ctx| {
+                let (old, wrote) =
+                    self.rmw(ctx, success, failure, |v| (v == current).then_some(new));
+                // a failed CAS writes nothing
+                (if wrote { Ok(old) } else { Err(old) }, wrote)
+            },
+            |r| {
+                format!(
+                    "{}.compare_exchange({current:?}, {new:?}, {success:?}, {failure:?}) -> {r:?}",
+                    self.name
+                )
+            },
+        )
+    }
+
+    // never fails spuriously here, that would only add more of the same interleavings
+    pub fn compare_exchange_weak(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        self.compare_exchange(current, new, success, failure)
+    }
+}
+
+impl Atomic<usize> {
+    pub fn fetch_add(&self, n: usize, order: Ordering) -> usize {
+        step(
+            |ctx| self.rmw(ctx, order, order, |v| Some(v.wrapping_add(n))),
+            |old| format!("{}.fetch_add({n}, {order:?}) -> {old}", self.name),
+        )
+    }
+}
+
+pub struct Failure {
+    pub message: String,
+    // the schedule that got there, one line per step
+    pub trace: Vec<String>,
+}
+
+pub struct Report {
+    pub name: String,
+    pub executions: usize,
+    // false if we hit max_executions before trying everything
+    pub complete: bool,
+    pub failure: Option<Failure>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.failure {
+            None if self.complete => write!(
+                f,
+                "{} : ok, all {} interleavings",
+                self.name, self.executions
+            ),
+            None => write!(
+                f,
+                "{} : ok so far, stopped after {} interleavings",
+                self.name, self.executions
+            ),
+            Some(failure) => {
+                writeln!(
+                    f,
+                    "{} : FAILED in interleaving {} : {}",
+                    self.name, self.executions, failure.message
+                )?;
+                for line in &failure.trace {
+                    writeln!(f, "    {line}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub struct Model {
+    // per run, guards against loops that don't spin through spin_loop()
+    pub max_steps: usize,
+    pub max_executions: usize,
+    // weak memory ( weak.rs ) instead of sequentially consistent
+    pub weak: bool,
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self {
+            max_steps: 1000,
+            max_executions: 100_000,
+            weak: false,
+        }
+    }
+}
+
+impl Model {
+    // setup builds fresh shared state for every run, each of the `threads` threads runs
+    // thread(&shared, id), then finally(&shared) checks the end result
+    pub fn check<S: Sync>(
+        &self,
+        name: &str,
+        threads: usize,
+        setup: impl Fn() -> S,
+        thread: impl Fn(&S, usize) + Sync,
+        finally: impl Fn(&S) -> Result<(), String>,
+    ) -> Report {
+        let mut prefix = Vec::new();
+        let mut executions = 0;
+        loop {
+            executions += 1;
+            let shared = setup();
+            let (trail, trace, failure) = self.run(&shared, threads, &thread, prefix);
+            let failure = failure.or_else(|| finally(&shared).err());
+            if let Some(message) = failure {
+                return Report {
+                    name: name.to_owned(),
+                    executions,
+                    complete: false,
+                    failure: Some(Failure { message, trace }),
+                };
+            }
+            match next_prefix(trail) {
+                Some(next) if executions < self.max_executions => prefix = next,
+                next => {
+                    return Report {
+                        name: name.to_owned(),
+                        executions,
+                        complete: next.is_none(),
+                        failure: None,
+                    }
+                }
+            }
+        }
+    }
+
+    fn run<S: Sync>(
+        &self,
+        shared: &S,
+        threads: usize,
+        thread: &(impl Fn(&S, usize) + Sync),
+        prefix: Vec<usize>,
+    ) -> (Vec<Choice>, Vec<String>, Option<String>) {
+        let exec = Arc::new(Exec {
+            state: StdMutex::new(State {
+                active: None,
+                status: vec![Status::Runnable; threads],
+                prefix,
+                trail: Vec::new(),
+                trace: Vec::new(),
+                inside: Vec::new(),
+                failure: None,
+                steps: 0,
+                finished: 0,
+                memory: self.weak.then(|| Memory::new(threads)),
+            }),
+            cv: Condvar::new(),
+            max_steps: self.max_steps,
+        });
+        thread::scope(|s| {
+            for me in 0..threads {
+                let exec = exec.clone();
+                s.spawn(move || model_thread(exec, me, || thread(shared, me)));
+            }
+            let mut state = exec.lock();
+            state.active = Exec::pick(&mut state);
+            exec.cv.notify_all();
+            while state.finished < threads {
+                state = exec.cv.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+        });
+        let mut state = exec.lock();
+        (
+            std::mem::take(&mut state.trail),
+            std::mem::take(&mut state.trace),
+            state.failure.take(),
+        )
+    }
+}
+
+fn model_thread(exec: Arc<Exec>, me: usize, body: impl FnOnce()) {
+    CURRENT.with(|c| *c.borrow_mut() = Some((exec.clone(), me)));
+    let r = panic::catch_unwind(AssertUnwindSafe(|| {
+        drop(exec.wait_turn(exec.lock(), me));
+        body();
+    }));
+    CURRENT.with(|c| *c.borrow_mut() = None);
+
+    let mut state = exec.lock();
+    match r {
+        Ok(()) => state.trace.push(format!("t{me}: done")),
+        Err(payload) if !payload.is::<Abort>() => {
+            state
+                .failure
+                .get_or_insert_with(|| format!("t{me} panicked : {}", panic_message(&*payload)));
+        }
+        Err(_) => {}
+    }
+    state.status[me] = Status::Done;
+    state.finished += 1;
+    if state.failure.is_none() && state.active == Some(me) {
+        state.active = Exec::pick(&mut state);
+        if state.active.is_none() && state.status.contains(&Status::Spinning) {
+            state.failure = Some("deadlock : every thread is spinning".to_owned());
+        }
+    }
+    exec.cv.notify_all();
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(s) => s,
+        None => payload.downcast_ref::<String>().map_or("?", |s| s),
+    }
+}
+
+// Depth first : bump the deepest choice that still has untried threads
+fn next_prefix(mut trail: Vec<Choice>) -> Option<Vec<usize>> {
+    while let Some(last) = trail.pop() {
+        if last.picked + 1 < last.options {
+            let mut prefix: Vec<usize> = trail.iter().map(|c| c.picked).collect();
+            prefix.push(last.picked + 1);
+            return Some(prefix);
+        }
+    }
+    None
+}
+
+// The with_lock steps from mutex.rs again, on model atomics
+
+pub fn with_lock_1(locked: &AtomicBool, f: &dyn Fn()) {
+    while locked.load(Ordering::Relaxed) != UNLOCKED {
+        spin_loop();
+    }
+    // <- the other thread can get here too before we store
+    locked.store(LOCKED, Ordering::Relaxed);
+    f();
+    locked.store(UNLOCKED, Ordering::Relaxed);
+}
+
+pub fn with_lock_2(locked: &AtomicBool, f: &dyn Fn()) {
+    while locked
+        .compare_exchange_weak(UNLOCKED, LOCKED, Ordering::Relaxed, Ordering::Relaxed)
+        .is_err()
+    {
+        while locked.load(Ordering::Relaxed) == LOCKED {
+            spin_loop();
+        }
+    }
+    f();
+    locked.store(UNLOCKED, Ordering::Relaxed);
+}
+
+pub fn with_lock_3(locked: &AtomicBool, f: &dyn Fn()) {
+    while locked
+        .compare_exchange_weak(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        while locked.load(Ordering::Relaxed) == LOCKED {
+            spin_loop();
+        }
+    }
+    f();
+    locked.store(UNLOCKED, Ordering::Release);
+}
+
+struct Counter {
+    locked: AtomicBool,
+    // plain data behind the lock, Relaxed is the most a non atomic access could promise
+    data: AtomicUsize,
+}
+
+const THREADS: usize = 2;
+
+// Two threads each add 1 under the lock
+pub fn check_with_lock(model: &Model, name: &str, with_lock: fn(&AtomicBool, &dyn Fn())) -> Report {
+    model.check(
+        name,
+        THREADS,
+        || Counter {
+            locked: AtomicBool::new("locked", UNLOCKED),
+            data: AtomicUsize::new("data", 0),
+        },
+        |c, _| {
+            with_lock(&c.locked, &|| {
+                let _inside = enter();
+                let v = c.data.load(Ordering::Relaxed);
+                c.data.store(v + 1, Ordering::Relaxed);
+            })
+        },
+        |c| match c.data.load(Ordering::Relaxed) {
+            THREADS => Ok(()),
+            n => Err(format!("lost update : data = {n}, expected {THREADS}")),
+        },
+    )
+}
+
+// `cargo run -- model`
+pub fn run() {
+    let model = Model::default();
+    println!("{}", check_with_lock(&model, "with_lock_1", with_lock_1));
+    // sequentially consistent here, the missing Acquire / Release only shows up with
+    // weak memory ( `cargo run -- weak` )
+    println!("{}", check_with_lock(&model, "with_lock_2", with_lock_2));
+    println!("{}", check_with_lock(&model, "with_lock_3", with_lock_3));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_with_lock_1_race() {
+        let report = check_with_lock(&Model::default(), "with_lock_1", with_lock_1);
+        assert!(report.failure.is_some(), "{report}");
+    }
+
+    #[test]
+    fn with_lock_2_and_3_are_fine_sequentially_consistent() {
+        for (name, with_lock) in [
+            ("with_lock_2", with_lock_2 as fn(&AtomicBool, &dyn Fn())),
+            ("with_lock_3", with_lock_3),
+        ] {
+            let report = check_with_lock(&Model::default(), name, with_lock);
+            assert!(report.failure.is_none() && report.complete, "{report}");
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+use std::thread;
+
+use crate::mutex::{Mutex, MutexGuard};
+use crate::raw::RawLock;
+
+// Taking several locks at once without deadlocking :
+//   thread 1 : a.lock() then b.lock()
+//   thread 2 : b.lock() then a.lock()   <- both wait for each other forever
+// lock_all((&a, &b)) goes in address order, so everybody agrees on who comes first.
+// On top of that it never blocks while holding something : block on one lock ( nothing
+// held yet ), only try_lock the rest, and if one is busy let go of everything and start
+// over by blocking on the busy one first ( so it still works next to code that locks the
+// same mutexes by hand in another order ).
+
+// Anything lock_all can take, implemented for &Mutex
+pub trait Lockable<'a> {
+    type Guard;
+    fn addr(&self) -> usize;
+    fn lock(&self) -> LockResult<Self::Guard>;
+    fn try_lock(&self) -> TryLockResult<Self::Guard>;
+}
+
+impl<'a, R: RawLock, T> Lockable<'a> for &'a Mutex<R, T> {
+    type Guard = MutexGuard<'a, R, T>;
+
+    fn addr(&self) -> usize {
+        *self as *const Mutex<R, T> as usize
+    }
+
+    #[track_caller]
+    fn lock(&self) -> LockResult<Self::Guard> {
+        Mutex::lock(self)
+    }
+
+    #[track_caller]
+    fn try_lock(&self) -> TryLockResult<Self::Guard> {
+        Mutex::try_lock(self)
+    }
+}
+
+// Tuples of Lockables, lock_all((&a, &b, &c)) gives back (guard_a, guard_b, guard_c)
+pub trait LockSet<'a> {
+    type Guards;
+    fn lock_all(self) -> LockResult<Self::Guards>;
+}
+
+// Poisoned if any of them is, the guards are inside the error like in Mutex::lock
+#[track_caller]
+pub fn lock_all<'a, S: LockSet<'a>>(locks: S) -> LockResult<S::Guards> {
+    locks.lock_all()
+}
+
+// Same as Mutex::with_lock : ignores poison, the guards unlock even if f panics
+#[track_caller]
+pub fn with_locks<'a, S: LockSet<'a>, U>(locks: S, f: impl FnOnce(S::Guards) -> U) -> U {
+    f(lock_all(locks).unwrap_or_else(PoisonError::into_inner))
+}
+
+// Indices sorted by address, same mutex twice can never be taken so we panic instead
+fn address_order<const N: usize>(addrs: [usize; N]) -> [usize; N] {
+    let mut order: [usize; N] = std::array::from_fn(|i| i);
+    order.sort_by_key(|&i| addrs[i]);
+    if order.windows(2).any(|w| addrs[w[0]] == addrs[w[1]]) {
+        panic!("lock_all got the same Mutex twice");
+    }
+    order
+}
+
+// ( guard, was it poisoned )
+fn split<G>(r: LockResult<G>) -> (G, bool) {
+    match r {
+        Ok(g) => (g, false),
+        Err(e) => (e.into_inner(), true),
+    }
+}
+
+// the nested $( )+ can't walk the guard list again, so it comes in a second time as one tt
+macro_rules! drop_all {
+    ([$($g:ident)+]) => {
+        $(drop($g.take());)+
+    };
+}
+
+macro_rules! lock_set {
+    ($n:literal; $all:tt; $($i:tt $L:ident $g:ident),+) => {
+        impl<'a, $($L: Lockable<'a>),+> LockSet<'a> for ($($L,)+) {
+            type Guards = ($($L::Guard,)+);
+
+            #[track_caller]
+            fn lock_all(self) -> LockResult<Self::Guards> {
+                let order = address_order::<$n>([$(self.$i.addr()),+]);
+                // the one we block on before taking anything else, first round that's
+                // the lowest address so it's plain address order
+                let mut first = order[0];
+                'retry: loop {
+                    $(let mut $g = None;)+
+                    let rest = order.iter().copied().filter(|&i| i != first);
+                    for i in std::iter::once(first).chain(rest) {
+                        $(
+                            if i == $i {
+                                let r = if i == first {
+                                    Lockable::lock(&self.$i)
+                                } else {
+                                    match Lockable::try_lock(&self.$i) {
+                                        Ok(g) => Ok(g),
+                                        Err(TryLockError::Poisoned(e)) => Err(e),
+                                        Err(TryLockError::WouldBlock) => {
+                                            // drop everything we have ( unlocks ) and
+                                            // wait for the busy one next round
+                                            drop_all!($all);
+                                            first = i;
+                                            thread::yield_now();
+                                            continue 'retry;
+                                        }
+                                    }
+                                };
+                                $g = Some(split(r));
+                            }
+                        )+
+                    }
+                    // every slot got filled above
+                    $(let $g = $g.unwrap();)+
+                    let guards = ($($g.0,)+);
+                    return if false $(|| $g.1)+ {
+                        Err(PoisonError::new(guards))
+                    } else {
+                        Ok(guards)
+                    };
+                }
+            }
+        }
+    };
+}
+
+lock_set!(2; [a b]; 0 A a, 1 B b);
+lock_set!(3; [a b c]; 0 A a, 1 B b, 2 C c);
+lock_set!(4; [a b c d]; 0 A a, 1 B b, 2 C c, 3 D d);
+lock_set!(5; [a b c d e]; 0 A a, 1 B b, 2 C c, 3 D d, 4 E e);
+lock_set!(6; [a b c d e f]; 0 A a, 1 B b, 2 C c, 3 D d, 4 E e, 5 F f);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backoff::SpinThenYield;
+    use crate::mutex::TtasLock;
+    use std::sync::Barrier;
+    use std::thread;
+
+    type Lock = Mutex<TtasLock<SpinThenYield>, usize>;
+
+    // lock_all in one thread, the same two mutexes by hand against address order in another
+    #[test]
+    fn no_deadlock_against_manual_locking() {
+        let (a, b) = (Lock::new(0), Lock::new(0));
+        let (lo, hi) = if (&a as *const Lock) < (&b as *const Lock) {
+            (&a, &b)
+        } else {
+            (&b, &a)
+        };
+        let start = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                start.wait();
+                for _ in 0..2000 {
+                    with_locks((&a, &b), |(mut a, mut b)| {
+                        *a += 1;
+                        *b += 1;
+                    });
+                }
+            });
+            s.spawn(|| {
+                start.wait();
+                for _ in 0..2000 {
+                    let mut x = hi.lock().unwrap();
+                    // let lock_all run into the held mutex
+                    thread::yield_now();
+                    let mut y = lo.lock().unwrap();
+                    *x += 1;
+                    *y += 1;
+                }
+            });
+        });
+        assert_eq!(*a.lock().unwrap(), 4000);
+        assert_eq!(*b.lock().unwrap(), 4000);
+    }
+}
@@ -0,0 +1,565 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+#[cfg(any(feature = "lockdep", feature = "stats", feature = "trace"))]
+use std::panic::Location;
+#[cfg(debug_assertions)]
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "stats")]
+use std::sync::Arc;
+use std::sync::{LockResult, TryLockError, TryLockResult};
+#[cfg(debug_assertions)]
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backoff::{Backoff, Spin};
+#[cfg(feature = "lockdep")]
+use crate::lockdep;
+use crate::poison;
+use crate::raw::RawLock;
+#[cfg(feature = "stats")]
+use crate::stats;
+#[cfg(debug_assertions)]
+use crate::thread_id;
+#[cfg(feature = "trace")]
+use crate::trace;
+
+const LOCKED: bool = true;
+const UNLOCKED: bool = false;
+
+// Data + whatever locking algorithm R implements ( see raw.rs )
+pub struct Mutex<R, T> {
+    raw: R,
+    poison: poison::Flag,
+    v: UnsafeCell<T>,
+    // thread_id::current() of the holder ( 0 if none ), only to catch self deadlocks
+    #[cfg(debug_assertions)]
+    owner: AtomicUsize,
+    #[cfg(feature = "lockdep")]
+    class: lockdep::Class,
+    #[cfg(feature = "stats")]
+    stats: Arc<stats::LockStats>,
+    #[cfg(feature = "trace")]
+    site: &'static Location<'static>,
+}
+
+// the spin lock from with_lock_3, for another backoff ( see backoff.rs ) spell it out :
+// Mutex::<TtasLock<Exponential>, _>::new(0)
+pub type SpinMutex<T> = Mutex<TtasLock, T>;
+
+// we know that Mutex is Sync
+unsafe impl<R: RawLock, T> Sync for Mutex<R, T> where T: Send {}
+
+impl<R: RawLock, T> Mutex<R, T> {
+    #[track_caller]
+    pub fn new(t: T) -> Self {
+        Self {
+            raw: R::new(),
+            poison: poison::Flag::new(),
+            v: UnsafeCell::new(t),
+            #[cfg(debug_assertions)]
+            owner: AtomicUsize::new(0),
+            #[cfg(feature = "lockdep")]
+            class: lockdep::register(Location::caller()),
+            #[cfg(feature = "stats")]
+            stats: stats::register(Location::caller(), std::any::type_name::<R>()),
+            #[cfg(feature = "trace")]
+            site: Location::caller(),
+        }
+    }
+
+    // Ignores poison : f runs even if an earlier holder panicked ( a panic in f still
+    // poisons ), check is_poisoned() or use lock() to find out
+    #[track_caller]
+    pub fn with_lock<U>(&self, f: impl FnOnce(&mut T) -> U) -> U {
+        self.acquire();
+        // we hold the lock, the guard unlocks ( with Release ) even if f panics
+        let mut guard = MutexGuard::new(self);
+        f(&mut guard)
+    }
+
+    // Lock is held until the guard is dropped
+    #[track_caller]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, R, T>> {
+        self.acquire();
+        poison::wrap(&self.poison, MutexGuard::new(self))
+    }
+
+    // Single attempt, gives up right away if someone else holds the lock
+    #[track_caller]
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, R, T>> {
+        if !self.raw.try_lock() {
+            return Err(TryLockError::WouldBlock);
+        }
+        #[cfg(feature = "stats")]
+        self.stats.acquired();
+        Ok(poison::wrap(&self.poison, MutexGuard::new(self))?)
+    }
+
+    #[track_caller]
+    pub fn try_lock_for(&self, timeout: Duration) -> TryLockResult<MutexGuard<'_, R, T>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_lock_until(deadline),
+            // timeout too big to represent, same as waiting forever
+            None => Ok(self.lock()?),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock_until(&self, deadline: Instant) -> TryLockResult<MutexGuard<'_, R, T>> {
+        // it can wait a long time too, so same checks as lock()
+        self.before_lock();
+        #[cfg(not(feature = "stats"))]
+        if !self.raw.try_lock_until(deadline) {
+            return Err(TryLockError::WouldBlock);
+        }
+        #[cfg(feature = "stats")]
+        {
+            let wait = stats::Wait::start();
+            // fast path first, see acquire()
+            let contended = !self.raw.try_lock();
+            if contended && !self.raw.try_lock_until(deadline) {
+                return Err(TryLockError::WouldBlock);
+            }
+            wait.finish(&self.stats, contended);
+        }
+        Ok(poison::wrap(&self.poison, MutexGuard::new(self))?)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+
+    // Some thread panicked while holding the lock
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    // Caller checked the data is fine again
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
+    // counters of every Mutex created at the same place with the same R ( see stats.rs )
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> stats::LockStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    #[cfg(feature = "trace")]
+    fn trace(&self, kind: trace::Kind) {
+        trace::record(kind, self as *const Self as usize, self.site);
+    }
+
+    #[track_caller]
+    fn acquire(&self) {
+        self.before_lock();
+        #[cfg(not(feature = "stats"))]
+        self.raw.lock();
+        #[cfg(feature = "stats")]
+        {
+            let wait = stats::Wait::start();
+            // try the fast path first so we know if we had to wait with every lock, only
+            // TAS / TTAS count their own spins
+            let contended = !self.raw.try_lock();
+            if contended {
+                self.raw.lock();
+            }
+            wait.finish(&self.stats, contended);
+        }
+    }
+
+    // Checks before a blocking lock.
+    // Locking again from the thread that holds the lock would spin ( or sleep ) forever,
+    // debug builds panic instead. Relaxed is enough : only we can store our own id.
+    #[track_caller]
+    fn before_lock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class, Location::caller());
+        #[cfg(feature = "trace")]
+        self.trace(trace::Kind::WaitBegin);
+        #[cfg(debug_assertions)]
+        if self.owner.load(Ordering::Relaxed) == thread_id::current() {
+            let thread = thread::current();
+            panic!(
+                "thread {} ({:?}) attempted to re-acquire Mutex it holds",
+                thread.name().unwrap_or("<unnamed>"),
+                thread.id()
+            );
+        }
+    }
+}
+
+// The three steps to a working spin lock, they poke at the TTAS lock word directly.
+// with_lock_1 used to be called `with_lock` : since every lock algorithm sits behind
+// Mutex, `with_lock` is the correct one on any of them and the racy first step got a number
+impl<T, B: Backoff> Mutex<TtasLock<B>, T> {
+    // We want to grab a lock and execute f
+    pub fn with_lock_1<U>(&self, f: impl FnOnce(&mut T) -> U) -> U {
+        while self.raw.locked.load(Ordering::Relaxed) != UNLOCKED {
+            std::hint::spin_loop(); // spin lock
+        }
+        // bug : maybe another thread runs here so it's possible for data race
+        // ( `cargo run -- model` finds such a schedule, see model.rs )
+        self.raw.locked.store(LOCKED, Ordering::Relaxed);
+        // Safety : we hold the lock so we can create mutable ref
+        let ret = f(unsafe { &mut *self.v.get() });
+        self.raw.locked.store(UNLOCKED, Ordering::Relaxed);
+        ret
+    }
+    // better implementation ( it still fails because of orderings )
+    // ( on x86 it looks fine, `cargo run -- weak` shows the stale read on weak memory )
+    pub fn with_lock_2<U>(&self, f: impl FnOnce(&mut T) -> U) -> U {
+        while self
+            .raw
+            .locked
+            .compare_exchange_weak(
+                // very inefficient but works ( all threads will fight to get that value )
+                UNLOCKED,
+                LOCKED,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            // spin lock
+            // MESI protocol
+            // more efficient waiting if we fail with compare_exchange_weak
+            while self.raw.locked.load(Ordering::Relaxed) == LOCKED {
+                std::hint::spin_loop();
+            }
+        }
+        // Safety : we hold the lock so we can create mutable ref
+        let ret = f(unsafe { &mut *self.v.get() });
+        self.raw.locked.store(UNLOCKED, Ordering::Relaxed);
+        ret
+    }
+
+    // Prevent reordering of operations with Orderings ( correct impl )
+    // that's exactly TtasLock::lock, so this is with_lock for this Mutex ( ignores
+    // poison the same way )
+    #[track_caller]
+    pub fn with_lock_3<U>(&self, f: impl FnOnce(&mut T) -> U) -> U {
+        self.acquire();
+        // we hold the lock, the guard unlocks ( with Release ) even if f panics
+        let mut guard = MutexGuard::new(self);
+        f(&mut guard)
+    }
+}
+
+// Holds the lock for as long as it lives, unlocks ( with Release ) on drop
+// Not Send : the debug owner and the lockdep held stack belong to the locking thread,
+// unlocking from another one would leave them pointing at the wrong thread
+pub struct MutexGuard<'a, R: RawLock, T> {
+    mutex: &'a Mutex<R, T>,
+    poison: poison::Guard,
+    #[cfg(feature = "stats")]
+    acquired_at: Instant,
+    not_send: PhantomData<*const ()>,
+}
+
+impl<'a, R: RawLock, T> MutexGuard<'a, R, T> {
+    // caller must already hold the lock
+    #[track_caller]
+    fn new(mutex: &'a Mutex<R, T>) -> Self {
+        #[cfg(debug_assertions)]
+        mutex.owner.store(thread_id::current(), Ordering::Relaxed);
+        #[cfg(feature = "lockdep")]
+        lockdep::acquired(mutex.class, Location::caller());
+        #[cfg(feature = "trace")]
+        mutex.trace(trace::Kind::Acquired);
+        Self {
+            mutex,
+            poison: mutex.poison.guard(),
+            #[cfg(feature = "stats")]
+            acquired_at: Instant::now(),
+            not_send: PhantomData,
+        }
+    }
+
+    // not a method so it doesn't hide methods of T behind Deref
+    pub fn mutex(guard: &Self) -> &'a Mutex<R, T> {
+        guard.mutex
+    }
+}
+
+// guard only hands out &T so sharing it between threads needs T: Sync
+unsafe impl<R: RawLock, T> Sync for MutexGuard<'_, R, T> where T: Sync {}
+
+impl<R: RawLock, T> Deref for MutexGuard<'_, R, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety : guard exists so we hold the lock
+        unsafe { &*self.mutex.v.get() }
+    }
+}
+
+impl<R: RawLock, T> DerefMut for MutexGuard<'_, R, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety : guard exists so we hold the lock
+        unsafe { &mut *self.mutex.v.get() }
+    }
+}
+
+impl<R: RawLock, T: fmt::Debug> fmt::Debug for MutexGuard<'_, R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<R: RawLock, T> Drop for MutexGuard<'_, R, T> {
+    fn drop(&mut self) {
+        // runs during unwind too, so a panic in the critical section poisons the lock
+        self.mutex.poison.done(&self.poison);
+        #[cfg(debug_assertions)]
+        self.mutex.owner.store(0, Ordering::Relaxed);
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.mutex.class);
+        #[cfg(feature = "stats")]
+        self.mutex.stats.released(self.acquired_at);
+        #[cfg(feature = "trace")]
+        self.mutex.trace(trace::Kind::Released);
+        // Safety : guard exists so we hold the lock
+        unsafe { self.mutex.raw.unlock() };
+    }
+}
+
+// Test-and-set : just keep swapping true in until we are the one that flipped it
+pub struct TasLock<B = Spin> {
+    locked: AtomicBool,
+    backoff: PhantomData<fn() -> B>,
+}
+
+unsafe impl<B: Backoff> RawLock for TasLock<B> {
+    fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(UNLOCKED),
+            backoff: PhantomData,
+        }
+    }
+
+    fn lock(&self) {
+        let mut backoff = B::default();
+        // every attempt is a write, so the cache line bounces between all waiters
+        while self.locked.swap(LOCKED, Ordering::Acquire) == LOCKED {
+            #[cfg(feature = "stats")]
+            stats::failed_cas();
+            backoff.snooze();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.locked.swap(LOCKED, Ordering::Acquire) == UNLOCKED
+    }
+
+    unsafe fn unlock(&self) {
+        // pairs with the Acquire swap of the next holder
+        self.locked.store(UNLOCKED, Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed) == LOCKED
+    }
+}
+
+// Test-and-test-and-set : the with_lock_3 protocol, only try the CAS when the lock
+// looks free, in between just read ( the cache line stays shared )
+pub struct TtasLock<B = Spin> {
+    locked: AtomicBool,
+    backoff: PhantomData<fn() -> B>,
+}
+
+unsafe impl<B: Backoff> RawLock for TtasLock<B> {
+    fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(UNLOCKED),
+            backoff: PhantomData,
+        }
+    }
+
+    fn lock(&self) {
+        let mut backoff = B::default();
+        while self
+            .locked
+            .compare_exchange_weak(
+                UNLOCKED,
+                LOCKED,
+                Ordering::Acquire, // <- We acquire here
+                Ordering::Relaxed, // <- We don't care in case of failure to acquire the lock
+            )
+            .is_err()
+        {
+            // spin lock
+            // MESI protocol
+            // more efficient waiting if we fail with compare_exchange
+            #[cfg(feature = "stats")]
+            stats::failed_cas();
+            while self.locked.load(Ordering::Relaxed) == LOCKED {
+                #[cfg(feature = "stats")]
+                stats::spin();
+                backoff.snooze();
+            }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.locked
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    // Spin like lock() but stop once deadline has passed
+    fn try_lock_until(&self, deadline: Instant) -> bool {
+        let mut backoff = B::default();
+        loop {
+            if self.try_lock() {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            while self.locked.load(Ordering::Relaxed) == LOCKED && Instant::now() < deadline {
+                backoff.snooze();
+            }
+        }
+    }
+
+    unsafe fn unlock(&self) {
+        self.locked.store(UNLOCKED, Ordering::Release); // <- Release here
+    }
+
+    fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed) == LOCKED
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clh::{RawClhLock, RawClhTimeoutLock};
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    use crate::futex::RawFutexLock;
+    use crate::mcs::RawMcsLock;
+    use crate::ticket::RawTicketLock;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread;
+
+    // ( count, someone inside )
+    fn enter(state: &mut (usize, bool)) {
+        assert!(!state.1, "two threads inside");
+        state.1 = true;
+        // give the others a chance to run into the lock while we hold it
+        thread::yield_now();
+        state.0 += 1;
+        state.1 = false;
+    }
+
+    // Threads go in through with_lock, try_lock and try_lock_for, never two at once
+    fn mutual_exclusion<R: RawLock>() {
+        let m = Mutex::<R, _>::new((0, false));
+        let entered: usize = thread::scope(|s| {
+            let handles: Vec<_> = (0..3)
+                .map(|t| {
+                    let m = &m;
+                    s.spawn(move || {
+                        let mut entered = 0;
+                        for i in 0..200 {
+                            match (t + i) % 3 {
+                                0 => m.with_lock(enter),
+                                1 => match m.try_lock() {
+                                    Ok(mut guard) => enter(&mut guard),
+                                    Err(_) => continue,
+                                },
+                                _ => match m.try_lock_for(Duration::from_millis(1)) {
+                                    Ok(mut guard) => enter(&mut guard),
+                                    Err(_) => continue,
+                                },
+                            }
+                            entered += 1;
+                        }
+                        entered
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+        assert!(!m.is_locked());
+        assert_eq!(m.lock().unwrap().0, entered);
+    }
+
+    #[test]
+    fn tas_mutual_exclusion() {
+        mutual_exclusion::<TasLock>();
+    }
+
+    #[test]
+    fn ttas_mutual_exclusion() {
+        mutual_exclusion::<TtasLock>();
+    }
+
+    #[test]
+    fn ticket_mutual_exclusion() {
+        mutual_exclusion::<RawTicketLock>();
+    }
+
+    #[test]
+    fn mcs_mutual_exclusion() {
+        mutual_exclusion::<RawMcsLock>();
+    }
+
+    #[test]
+    fn clh_mutual_exclusion() {
+        mutual_exclusion::<RawClhLock>();
+    }
+
+    #[test]
+    fn clh_timeout_mutual_exclusion() {
+        mutual_exclusion::<RawClhTimeoutLock>();
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    #[test]
+    fn futex_mutual_exclusion() {
+        mutual_exclusion::<RawFutexLock>();
+    }
+
+    #[test]
+    fn panic_in_with_lock_poisons() {
+        let m = SpinMutex::new(0);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            m.with_lock(|v| {
+                *v += 1;
+                panic!("boom");
+            })
+        }));
+        assert!(res.is_err());
+        assert!(m.is_poisoned());
+        assert!(!m.is_locked());
+        // the data is still there behind the error
+        assert_eq!(*m.lock().unwrap_err().into_inner(), 1);
+        assert!(matches!(m.try_lock(), Err(TryLockError::Poisoned(_))));
+        // with_lock doesn't care
+        assert_eq!(m.with_lock(|v| *v), 1);
+        m.clear_poison();
+        assert!(!m.is_poisoned());
+        assert_eq!(*m.lock().unwrap(), 1);
+        assert!(m.try_lock().is_ok());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "attempted to re-acquire Mutex it holds")]
+    fn relocking_on_the_same_thread_panics() {
+        let m = SpinMutex::new(0);
+        let _guard = m.lock().unwrap();
+        let _again = m.lock();
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LockResult, PoisonError};
+use std::thread;
+
+// Set when a thread panics while holding a lock, so the next user knows the data
+// may be half updated. Relaxed is enough here, the lock itself orders everything.
+pub struct Flag {
+    failed: AtomicBool,
+}
+
+// Remembers if we were already panicking when the lock was taken,
+// we only want to poison for panics that happened inside the critical section
+pub struct Guard {
+    panicking: bool,
+}
+
+impl Flag {
+    pub const fn new() -> Self {
+        Self {
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn guard(&self) -> Guard {
+        Guard {
+            panicking: thread::panicking(),
+        }
+    }
+
+    // Called on unlock ( also during unwind )
+    pub fn done(&self, guard: &Guard) {
+        if !guard.panicking && thread::panicking() {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn get(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub fn clear(&self) {
+        self.failed.store(false, Ordering::Relaxed);
+    }
+}
+
+// Hand out the lock guard either way, the caller decides if it wants to recover
+pub fn wrap<G>(flag: &Flag, guard: G) -> LockResult<G> {
+    if flag.get() {
+        Err(PoisonError::new(guard))
+    } else {
+        Ok(guard)
+    }
+}
@@ -0,0 +1,37 @@
+use std::time::Instant;
+
+/// Just the locking algorithm, no data. Mutex<R, T> puts the data next to it.
+///
+/// # Safety
+/// Implementors must guarantee that
+/// - at most one thread holds the lock at a time
+/// - a successful lock / try_lock is an Acquire and unlock is a Release,
+///   so everything done while holding the lock is visible to the next holder
+pub unsafe trait RawLock: Send + Sync {
+    fn new() -> Self;
+
+    fn lock(&self);
+
+    // Single attempt, true if we got the lock
+    fn try_lock(&self) -> bool;
+
+    // Keep trying until deadline, true if we got the lock.
+    // Default just polls try_lock, queue locks override it if they can wait smarter.
+    fn try_lock_until(&self, deadline: Instant) -> bool {
+        loop {
+            if self.try_lock() {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    // Safety : the current thread must hold the lock
+    unsafe fn unlock(&self);
+
+    // Only a hint, it can change right after we look
+    fn is_locked(&self) -> bool;
+}
@@ -0,0 +1,160 @@
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::mutex::TtasLock;
+use crate::raw::RawLock;
+use crate::thread_id;
+
+// A thread that already holds it can lock it again ( with_lock_3 would just spin forever ).
+// Because the same thread can have several guards at once they only give out &T,
+// put a Cell / RefCell inside if you need to change things.
+pub struct ReentrantMutex<T> {
+    raw: TtasLock,
+    // thread_id::current() of the holder, 0 if nobody
+    owner: AtomicUsize,
+    // only touched by the owner
+    count: Cell<usize>,
+    v: UnsafeCell<T>,
+}
+
+// guards of one thread share &T, but the lock hands T between threads
+unsafe impl<T> Sync for ReentrantMutex<T> where T: Send {}
+
+impl<T> ReentrantMutex<T> {
+    pub fn new(t: T) -> Self {
+        Self {
+            raw: TtasLock::new(),
+            owner: AtomicUsize::new(0),
+            count: Cell::new(0),
+            v: UnsafeCell::new(t),
+        }
+    }
+
+    pub fn with_lock<U>(&self, f: impl FnOnce(&T) -> U) -> U {
+        f(&self.lock())
+    }
+
+    pub fn lock(&self) -> ReentrantMutexGuard<'_, T> {
+        let me = thread_id::current();
+        // Relaxed is enough : only we could have stored our own id, so if we see it
+        // we hold the lock, if we don't it can't turn into our id while we look
+        if self.owner.load(Ordering::Relaxed) == me {
+            self.count.set(self.count.get() + 1);
+        } else {
+            self.raw.lock();
+            self.owner.store(me, Ordering::Relaxed);
+            self.count.set(1);
+        }
+        ReentrantMutexGuard {
+            lock: self,
+            not_send: PhantomData,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<ReentrantMutexGuard<'_, T>> {
+        let me = thread_id::current();
+        if self.owner.load(Ordering::Relaxed) == me {
+            self.count.set(self.count.get() + 1);
+        } else if self.raw.try_lock() {
+            self.owner.store(me, Ordering::Relaxed);
+            self.count.set(1);
+        } else {
+            return None;
+        }
+        Some(ReentrantMutexGuard {
+            lock: self,
+            not_send: PhantomData,
+        })
+    }
+
+    fn unlock(&self) {
+        let count = self.count.get() - 1;
+        self.count.set(count);
+        if count == 0 {
+            self.owner.store(0, Ordering::Relaxed);
+            // Safety : we are the owner and this was our last guard
+            unsafe { self.raw.unlock() };
+        }
+    }
+}
+
+pub struct ReentrantMutexGuard<'a, T> {
+    lock: &'a ReentrantMutex<T>,
+    // must be dropped by the thread that locked, count belongs to the owner
+    not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<T> Sync for ReentrantMutexGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for ReentrantMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety : we hold the lock, other guards of this thread only have &T too
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ReentrantMutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for ReentrantMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn nested_locks_across_threads() {
+        let m = ReentrantMutex::new(Cell::new(0));
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    for _ in 0..200 {
+                        let outer = m.lock();
+                        outer.set(outer.get() + 1);
+                        let inner = m.lock();
+                        inner.set(inner.get() + 1);
+                        // we hold it, so try_lock can't fail
+                        let innermost = m.try_lock().expect("owner try_lock");
+                        innermost.set(innermost.get() + 1);
+                        drop(inner);
+                        drop(innermost);
+                        thread::yield_now();
+                        // still ours until the last guard goes
+                        outer.set(outer.get() + 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(m.lock().get(), 3 * 200 * 4);
+    }
+
+    #[test]
+    fn other_threads_wait_for_the_last_guard() {
+        let m = ReentrantMutex::new(());
+        let outer = m.lock();
+        let inner = m.lock();
+        thread::scope(|s| {
+            s.spawn(|| assert!(m.try_lock().is_none()));
+        });
+        drop(inner);
+        thread::scope(|s| {
+            s.spawn(|| assert!(m.try_lock().is_none()));
+        });
+        drop(outer);
+        thread::scope(|s| {
+            s.spawn(|| assert!(m.try_lock().is_some()));
+        });
+    }
+}
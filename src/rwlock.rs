@@ -0,0 +1,388 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Whole lock state in one word, like `locked` in Mutex but with room for readers
+const WRITER: usize = 1; // someone holds it exclusively
+const UPGRADABLE: usize = 1 << 1; // the one reader that is allowed to become a writer
+const WRITER_WAITING: usize = 1 << 2; // a writer is spinning, new readers should back off
+const READER: usize = 1 << 3; // one reader, the count lives in the remaining bits
+
+// Who goes first when readers and writers fight over the lock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preference {
+    // readers keep coming in while others read, writers can starve
+    Reader,
+    // a waiting writer stops new readers, readers can starve
+    Writer,
+}
+
+pub struct RwLock<T> {
+    state: AtomicUsize,
+    preference: Preference,
+    v: UnsafeCell<T>,
+}
+
+// readers share &T between threads so T has to be Sync too
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+impl<T> RwLock<T> {
+    pub fn new(t: T) -> Self {
+        Self::with_preference(t, Preference::Writer)
+    }
+
+    pub fn with_preference(t: T, preference: Preference) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            preference,
+            v: UnsafeCell::new(t),
+        }
+    }
+
+    pub fn with_read<U>(&self, f: impl FnOnce(&T) -> U) -> U {
+        f(&self.read())
+    }
+
+    pub fn with_write<U>(&self, f: impl FnOnce(&mut T) -> U) -> U {
+        f(&mut self.write())
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        while !self.raw_try_read() {
+            std::hint::spin_loop();
+        }
+        RwLockReadGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.raw_try_read().then(|| RwLockReadGuard { lock: self })
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            if self.raw_try_write() {
+                return RwLockWriteGuard { lock: self };
+            }
+            // let readers know we are here ( the bit is cleared by whoever gets the lock,
+            // other waiting writers just set it again )
+            if self.preference == Preference::Writer {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            while self.state.load(Ordering::Relaxed) & !WRITER_WAITING != 0 {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.raw_try_write()
+            .then(|| RwLockWriteGuard { lock: self })
+    }
+
+    // Read, decide, maybe write : f gets the upgradable guard and can call upgrade() on it
+    pub fn with_upgradable_read<U>(
+        &self,
+        f: impl FnOnce(RwLockUpgradableReadGuard<'_, T>) -> U,
+    ) -> U {
+        f(self.upgradable_read())
+    }
+
+    // Shares the lock with plain readers, but only one upgradable reader at a time
+    // ( two of them upgrading would wait for each other forever )
+    pub fn upgradable_read(&self) -> RwLockUpgradableReadGuard<'_, T> {
+        while !self.raw_try_upgradable_read() {
+            std::hint::spin_loop();
+        }
+        RwLockUpgradableReadGuard { lock: self }
+    }
+
+    pub fn try_upgradable_read(&self) -> Option<RwLockUpgradableReadGuard<'_, T>> {
+        self.raw_try_upgradable_read()
+            .then(|| RwLockUpgradableReadGuard { lock: self })
+    }
+
+    fn raw_try_read(&self) -> bool {
+        let mut blocked_by = WRITER;
+        if self.preference == Preference::Writer {
+            blocked_by |= WRITER_WAITING;
+        }
+        // other readers coming and going change the count, that's no reason to fail
+        let mut state = self.state.load(Ordering::Relaxed);
+        while state & blocked_by == 0 {
+            match self.state.compare_exchange_weak(
+                state,
+                state + READER,
+                Ordering::Acquire, // <- pairs with Release in write unlock
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => state = current,
+            }
+        }
+        false
+    }
+
+    fn raw_try_write(&self) -> bool {
+        let state = self.state.load(Ordering::Relaxed);
+        // free apart from maybe the waiting bit, taking it clears that bit
+        state & !WRITER_WAITING == 0
+            && self
+                .state
+                .compare_exchange(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    fn raw_try_upgradable_read(&self) -> bool {
+        let mut blocked_by = WRITER | UPGRADABLE;
+        if self.preference == Preference::Writer {
+            blocked_by |= WRITER_WAITING;
+        }
+        // same as raw_try_read, plain readers changing the count is no reason to fail
+        let mut state = self.state.load(Ordering::Relaxed);
+        while state & blocked_by == 0 {
+            match self.state.compare_exchange_weak(
+                state,
+                state | UPGRADABLE,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => state = current,
+            }
+        }
+        false
+    }
+
+    // Only plain readers may be left besides us, once they are gone we swap in WRITER
+    fn raw_try_upgrade(&self) -> bool {
+        let state = self.state.load(Ordering::Relaxed);
+        state & !WRITER_WAITING == UPGRADABLE
+            && self
+                .state
+                .compare_exchange(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety : we hold a read lock, nobody writes
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Release, a writer may only start after all our reads are done
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+unsafe impl<T> Sync for RwLockWriteGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety : we hold the write lock
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety : we hold the write lock
+        unsafe { &mut *self.lock.v.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T> RwLockWriteGuard<'a, T> {
+    // Become a plain reader without letting another writer in between
+    pub fn downgrade(self) -> RwLockReadGuard<'a, T> {
+        let lock = self.lock;
+        mem::forget(self);
+        // WRITER is set so subtracting it can't borrow, one RMW does both steps
+        lock.state.fetch_add(READER - WRITER, Ordering::Release);
+        RwLockReadGuard { lock }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // keep WRITER_WAITING, another writer may have set it meanwhile
+        // Release pairs with the Acquire CAS of the next reader / writer
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+pub struct RwLockUpgradableReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> RwLockUpgradableReadGuard<'a, T> {
+    // Waits for the plain readers to leave, we never let go of the lock in between
+    // so what we read before is still true after
+    pub fn upgrade(self) -> RwLockWriteGuard<'a, T> {
+        let lock = self.lock;
+        mem::forget(self);
+        loop {
+            if lock.raw_try_upgrade() {
+                return RwLockWriteGuard { lock };
+            }
+            // same as write(), stop new readers from keeping us waiting
+            if lock.preference == Preference::Writer {
+                lock.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            while lock.state.load(Ordering::Relaxed) & !WRITER_WAITING != UPGRADABLE {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    // Gives the guard back if there are still readers
+    pub fn try_upgrade(self) -> Result<RwLockWriteGuard<'a, T>, Self> {
+        if self.lock.raw_try_upgrade() {
+            let lock = self.lock;
+            mem::forget(self);
+            Ok(RwLockWriteGuard { lock })
+        } else {
+            Err(self)
+        }
+    }
+
+    // Let another upgradable reader in, we stay a plain reader
+    pub fn downgrade(self) -> RwLockReadGuard<'a, T> {
+        let lock = self.lock;
+        mem::forget(self);
+        // UPGRADABLE is set so subtracting it can't borrow
+        lock.state.fetch_add(READER - UPGRADABLE, Ordering::Release);
+        RwLockReadGuard { lock }
+    }
+}
+
+impl<T> Deref for RwLockUpgradableReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety : we hold a read lock, nobody writes
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwLockUpgradableReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for RwLockUpgradableReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(UPGRADABLE, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // Writers keep both counters equal, every guard checks they are
+    fn mixed(preference: Preference) {
+        let lock = RwLock::with_preference((0u64, 0u64), preference);
+        let check = |pair: &(u64, u64)| assert_eq!(pair.0, pair.1, "{preference:?}");
+        let bump = |pair: &mut (u64, u64)| {
+            pair.0 += 1;
+            thread::yield_now();
+            pair.1 += 1;
+        };
+        let writes: u64 = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|t| {
+                    let lock = &lock;
+                    s.spawn(move || {
+                        let mut writes = 0;
+                        for i in 0..300 {
+                            match (t + i) % 6 {
+                                0 => check(&lock.read()),
+                                1 => {
+                                    let mut w = lock.write();
+                                    check(&w);
+                                    bump(&mut w);
+                                    writes += 1;
+                                }
+                                2 => {
+                                    let u = lock.upgradable_read();
+                                    check(&u);
+                                    let mut w = u.upgrade();
+                                    bump(&mut w);
+                                    writes += 1;
+                                    let r = w.downgrade();
+                                    check(&r);
+                                }
+                                3 => match lock.upgradable_read().try_upgrade() {
+                                    Ok(mut w) => {
+                                        bump(&mut w);
+                                        writes += 1;
+                                    }
+                                    Err(u) => check(&u.downgrade()),
+                                },
+                                4 => {
+                                    if let Some(r) = lock.try_read() {
+                                        check(&r);
+                                    }
+                                    if let Some(u) = lock.try_upgradable_read() {
+                                        check(&u);
+                                    }
+                                }
+                                _ => {
+                                    if let Some(mut w) = lock.try_write() {
+                                        bump(&mut w);
+                                        writes += 1;
+                                    }
+                                }
+                            }
+                        }
+                        writes
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+        let pair = lock.read();
+        check(&pair);
+        assert_eq!(pair.0, writes);
+        drop(pair);
+        assert_eq!(lock.state.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn mixed_guards_keep_the_invariant_writer_preferred() {
+        mixed(Preference::Writer);
+    }
+
+    #[test]
+    fn mixed_guards_keep_the_invariant_reader_preferred() {
+        mixed(Preference::Reader);
+    }
+}
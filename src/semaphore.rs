@@ -0,0 +1,170 @@
+use std::mem;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::futex;
+
+// Counting semaphore : `permits` is the futex word, so waiters can sleep on it directly.
+// Like RawFutexLock we spin a little first and only then park.
+pub struct Semaphore {
+    permits: AtomicU32,
+    // how many threads are (about to be) asleep, release skips the syscall when 0
+    waiters: AtomicU32,
+}
+
+impl Semaphore {
+    pub const fn new(permits: u32) -> Self {
+        Self {
+            permits: AtomicU32::new(permits),
+            waiters: AtomicU32::new(0),
+        }
+    }
+
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        self.acquire_many(1)
+    }
+
+    // Takes all n at once ( never holds some while waiting for the rest )
+    pub fn acquire_many(&self, n: u32) -> SemaphorePermit<'_> {
+        loop {
+            if self.try_take(n) {
+                return SemaphorePermit { sem: self, n };
+            }
+            let mut spins = futex::SPIN_LIMIT;
+            while self.permits.load(Ordering::Relaxed) < n && spins > 0 {
+                std::hint::spin_loop();
+                spins -= 1;
+            }
+            if spins > 0 {
+                continue;
+            }
+            // SeqCst on both sides ( here and in release ) : either release sees us in
+            // `waiters` or we see its new permits, never neither
+            self.waiters.fetch_add(1, Ordering::SeqCst);
+            let permits = self.permits.load(Ordering::SeqCst);
+            if permits < n {
+                futex::wait(&self.permits, permits, None);
+            }
+            self.waiters.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, n: u32) -> Option<SemaphorePermit<'_>> {
+        self.try_take(n).then(|| SemaphorePermit { sem: self, n })
+    }
+
+    // Gives back one permit, for permits that were forgotten ( or to grow the semaphore )
+    pub fn release(&self) {
+        self.release_many(1);
+    }
+
+    // Panics if that would make more than u32::MAX permits
+    pub fn release_many(&self, n: u32) {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        loop {
+            let new = permits
+                .checked_add(n)
+                .expect("semaphore permit count overflowed");
+            // Release ( part of SeqCst ) : the next holder sees what we did with the permit
+            match self.permits.compare_exchange_weak(
+                permits,
+                new,
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => permits = current,
+            }
+        }
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            // waiters may want different amounts, let all of them re-check
+            futex::wake(&self.permits, i32::MAX);
+        }
+    }
+
+    pub fn available_permits(&self) -> u32 {
+        self.permits.load(Ordering::Relaxed)
+    }
+
+    fn try_take(&self, n: u32) -> bool {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits >= n {
+            // Acquire pairs with the release of whoever gave the permits back
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - n,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => permits = current,
+            }
+        }
+        false
+    }
+}
+
+// Gives its permits back when dropped
+pub struct SemaphorePermit<'a> {
+    sem: &'a Semaphore,
+    n: u32,
+}
+
+impl SemaphorePermit<'_> {
+    pub fn permits(&self) -> u32 {
+        self.n
+    }
+
+    // Keep the permits taken, Semaphore::release can return them later
+    pub fn forget(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.sem.release_many(self.n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn never_more_than_the_permits_held() {
+        let sem = Semaphore::new(3);
+        let held = AtomicU32::new(0);
+        thread::scope(|s| {
+            for t in 0..6 {
+                let (sem, held) = (&sem, &held);
+                s.spawn(move || {
+                    for i in 0..500 {
+                        let permit = if (t + i) % 2 == 0 {
+                            sem.acquire()
+                        } else {
+                            sem.acquire_many(2)
+                        };
+                        let now =
+                            held.fetch_add(permit.permits(), Ordering::SeqCst) + permit.permits();
+                        assert!(now <= 3, "{now} permits held");
+                        thread::yield_now();
+                        held.fetch_sub(permit.permits(), Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+        assert_eq!(sem.available_permits(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "semaphore permit count overflowed")]
+    fn release_past_u32_max_panics() {
+        let sem = Semaphore::new(u32::MAX - 1);
+        sem.release_many(2);
+    }
+}
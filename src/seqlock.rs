@@ -0,0 +1,107 @@
+use std::cell::UnsafeCell;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use crate::mutex::SpinMutex;
+
+// Readers never write shared memory : they read the sequence number, copy the value,
+// read the sequence again and retry if a writer was busy in between.
+// Odd sequence = write in progress. Only for small Copy data, a torn copy is just thrown away.
+pub struct SeqLock<T: Copy> {
+    seq: AtomicUsize,
+    // writers still need to exclude each other
+    writer: SpinMutex<()>,
+    v: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy> Sync for SeqLock<T> where T: Send {}
+
+impl<T: Copy> SeqLock<T> {
+    pub fn new(t: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            writer: SpinMutex::new(()),
+            v: UnsafeCell::new(t),
+        }
+    }
+
+    pub fn read(&self) -> T {
+        loop {
+            // Acquire pairs with the Release store that ends a write
+            let before = self.seq.load(Ordering::Acquire);
+            if before & 1 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            // Safety : T is Copy, a torn value is never used ( see the check below ).
+            // volatile so the compiler really reads it here and doesn't merge reads
+            let v = unsafe { ptr::read_volatile(self.v.get()) };
+            // keep the data read above the second seq read
+            fence(Ordering::Acquire);
+            let after = self.seq.load(Ordering::Relaxed);
+            if before == after {
+                return v;
+            }
+        }
+    }
+
+    pub fn write(&self, t: T) {
+        self.update(|v| *v = t);
+    }
+
+    // f works on a copy, so a panic in f leaves the old value and an even sequence
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        self.writer.with_lock_3(|_| {
+            // Safety : we are the only writer
+            let mut v = unsafe { ptr::read(self.v.get()) };
+            f(&mut v);
+
+            let seq = self.seq.load(Ordering::Relaxed);
+            self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+            // readers that see our data also see the odd sequence above
+            fence(Ordering::Release);
+            // Safety : readers may copy it concurrently but throw the copy away
+            unsafe { ptr::write_volatile(self.v.get(), v) };
+            // Release pairs with the Acquire load of `before` in read
+            self.seq.store(seq.wrapping_add(2), Ordering::Release);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn readers_never_see_a_torn_pair() {
+        let lock = SeqLock::new((0u64, 0u64));
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..2000 {
+                        lock.update(|(a, b)| {
+                            *a += 1;
+                            *b += 1;
+                        });
+                        // let the readers in between writes
+                        thread::yield_now();
+                    }
+                });
+            }
+            for _ in 0..2 {
+                s.spawn(|| {
+                    let mut last = 0;
+                    for _ in 0..2000 {
+                        let (a, b) = lock.read();
+                        assert_eq!(a, b, "torn read");
+                        // one writer at a time, the value only grows
+                        assert!(a >= last);
+                        last = a;
+                    }
+                });
+            }
+        });
+        assert_eq!(lock.read(), (4000, 4000));
+    }
+}
@@ -0,0 +1,277 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+
+// Per Mutex contention counters, a Mutex is named by where Mutex::new was called.
+// Like lockdep classes all mutexes created by the same line ( with the same lock
+// algorithm ) share their counters, so they are still there after the mutexes are gone
+// and a Mutex::new in a loop doesn't grow the registry.
+// All counters are Relaxed, they are just numbers, nothing is ordered by them.
+
+type Site = &'static Location<'static>;
+
+// one bucket per power of two nanoseconds, bucket i holds [2^(i-1), 2^i)
+const BUCKETS: usize = 64;
+
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    sum_ns: AtomicU64,
+}
+
+#[derive(Clone, Debug)]
+pub struct HistogramSnapshot {
+    pub buckets: [u64; BUCKETS],
+    pub sum_ns: u64,
+}
+
+pub struct LockStats {
+    site: Site,
+    lock: &'static str,
+    acquisitions: AtomicU64,
+    // acquisitions that had to wait at all
+    contended: AtomicU64,
+    failed_cas: AtomicU64,
+    spins: AtomicU64,
+    wait: Histogram,
+    hold: Histogram,
+}
+
+#[derive(Clone, Debug)]
+pub struct LockStatsSnapshot {
+    pub site: Site,
+    // RawLock type behind the Mutex
+    pub lock: &'static str,
+    pub acquisitions: u64,
+    pub contended: u64,
+    pub failed_cas: u64,
+    pub spins: u64,
+    pub wait: HistogramSnapshot,
+    pub hold: HistogramSnapshot,
+}
+
+// ( site, lock type ) -> counters shared by every Mutex created there
+static REGISTRY: StdMutex<BTreeMap<(Site, &'static str), Arc<LockStats>>> =
+    StdMutex::new(BTreeMap::new());
+
+thread_local! {
+    // the raw locks bump these while spinning, Wait::finish moves them to the Mutex
+    static SPINS: Cell<u64> = const { Cell::new(0) };
+    static FAILED_CAS: Cell<u64> = const { Cell::new(0) };
+}
+
+pub fn register(site: Site, lock: &'static str) -> Arc<LockStats> {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry
+        .entry((site, lock))
+        .or_insert_with(|| {
+            Arc::new(LockStats {
+                site,
+                lock,
+                acquisitions: AtomicU64::new(0),
+                contended: AtomicU64::new(0),
+                failed_cas: AtomicU64::new(0),
+                spins: AtomicU64::new(0),
+                wait: Histogram::new(),
+                hold: Histogram::new(),
+            })
+        })
+        .clone()
+}
+
+// Every class that was ever created, the ones that waited longest in total first
+pub fn registry() -> Vec<LockStatsSnapshot> {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let mut all: Vec<_> = registry.values().map(|s| s.snapshot()).collect();
+    all.sort_by_key(|s| std::cmp::Reverse(s.wait.sum_ns));
+    all
+}
+
+// called by raw locks, once per loop iteration while the lock is taken
+pub fn spin() {
+    SPINS.with(|c| c.set(c.get() + 1));
+}
+
+pub fn failed_cas() {
+    FAILED_CAS.with(|c| c.set(c.get() + 1));
+}
+
+// Started right before a blocking lock, finished once we have it
+pub struct Wait {
+    start: Instant,
+}
+
+impl Wait {
+    pub fn start() -> Self {
+        SPINS.with(|c| c.set(0));
+        FAILED_CAS.with(|c| c.set(0));
+        Self {
+            start: Instant::now(),
+        }
+    }
+
+    // contended : the fast path try_lock failed
+    pub fn finish(self, stats: &LockStats, contended: bool) {
+        let spins = SPINS.with(Cell::take);
+        let failed_cas = FAILED_CAS.with(Cell::take);
+        stats.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if contended || spins > 0 || failed_cas > 0 {
+            stats.contended.fetch_add(1, Ordering::Relaxed);
+        }
+        stats.spins.fetch_add(spins, Ordering::Relaxed);
+        stats.failed_cas.fetch_add(failed_cas, Ordering::Relaxed);
+        stats.wait.record(self.start.elapsed());
+    }
+}
+
+impl LockStats {
+    // try_lock that worked, nothing to wait for
+    pub fn acquired(&self) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn released(&self, acquired_at: Instant) {
+        self.hold.record(acquired_at.elapsed());
+    }
+
+    pub fn snapshot(&self) -> LockStatsSnapshot {
+        LockStatsSnapshot {
+            site: self.site,
+            lock: self.lock,
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+            failed_cas: self.failed_cas.load(Ordering::Relaxed),
+            spins: self.spins.load(Ordering::Relaxed),
+            wait: self.wait.snapshot(),
+            hold: self.hold.snapshot(),
+        }
+    }
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_ns: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, d: Duration) {
+        let ns = d.as_nanos().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - ns.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.sum_ns.fetch_add(ns, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed)),
+            sum_ns: self.sum_ns.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl HistogramSnapshot {
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    pub fn mean(&self) -> Duration {
+        Duration::from_nanos(self.sum_ns.checked_div(self.count()).unwrap_or(0))
+    }
+
+    // Upper bound of the bucket the p-th percentile falls in ( p in 0..=100 )
+    pub fn percentile(&self, p: f64) -> Duration {
+        let target = (self.count() as f64 * p / 100.0).ceil() as u64;
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if n > 0 && seen >= target {
+                return Duration::from_nanos(1u64.checked_shl(i as u32).unwrap_or(u64::MAX));
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+impl fmt::Display for LockStatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Mutex<{}> created at {}",
+            short_type_name(self.lock),
+            self.site
+        )?;
+        writeln!(
+            f,
+            "  acquisitions {} ( contended {} ), failed CAS {}, spins {}",
+            self.acquisitions, self.contended, self.failed_cas, self.spins
+        )?;
+        for (name, h) in [("wait", &self.wait), ("hold", &self.hold)] {
+            writeln!(
+                f,
+                "  {name} : total {:?}, mean {:?}, p50 <= {:?}, p99 <= {:?}",
+                Duration::from_nanos(h.sum_ns),
+                h.mean(),
+                h.percentile(50.0),
+                h.percentile(99.0)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// atomics::mutex::TtasLock<atomics::backoff::Spin> -> TtasLock<Spin>
+fn short_type_name(name: &str) -> String {
+    let mut out = String::new();
+    let mut word = String::new();
+    for c in name.chars() {
+        match c {
+            c if c.is_alphanumeric() || c == '_' => word.push(c),
+            // word was a module
+            ':' => word.clear(),
+            c => {
+                out += &word;
+                word.clear();
+                out.push(c);
+            }
+        }
+    }
+    out + &word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mutex::Mutex;
+    use crate::ticket::RawTicketLock;
+    use std::thread;
+    use std::time::Duration;
+
+    // the ticket lock doesn't count spins, waiting must still show up as contended
+    #[test]
+    fn waiting_counts_as_contended_for_any_lock() {
+        let m = Mutex::<RawTicketLock, u32>::new(0);
+        let guard = m.lock().unwrap();
+        thread::scope(|s| {
+            s.spawn(|| m.with_lock(|v| *v += 1));
+            thread::sleep(Duration::from_millis(20));
+            drop(guard);
+        });
+        m.with_lock(|v| *v += 1);
+        let stats = m.stats();
+        assert_eq!(stats.acquisitions, 3);
+        assert_eq!(stats.contended, 1);
+    }
+
+    #[test]
+    fn short_names() {
+        assert_eq!(
+            short_type_name("atomics::mutex::TtasLock<atomics::backoff::Spin>"),
+            "TtasLock<Spin>"
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::mutex::SpinMutex;
+
+// N threads each add 1 to a counter M times through one of the with_lock steps.
+// A correct lock ends at N * M, anything less is updates lost to the race.
+// Plain Spin backoff : with_lock_1 / 2 ignore the backoff and just spin_loop, so with_lock_3
+// waits the same way and the throughput numbers compare only the protocols.
+type Counter = SpinMutex<usize>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    WithLock1,
+    WithLock2,
+    WithLock3,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 3] = [Variant::WithLock1, Variant::WithLock2, Variant::WithLock3];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::WithLock1 => "with_lock_1",
+            Variant::WithLock2 => "with_lock_2",
+            Variant::WithLock3 => "with_lock_3",
+        }
+    }
+
+    fn increment(self, counter: &Counter) {
+        match self {
+            Variant::WithLock1 => counter.with_lock_1(|v| *v += 1),
+            Variant::WithLock2 => counter.with_lock_2(|v| *v += 1),
+            Variant::WithLock3 => counter.with_lock_3(|v| *v += 1),
+        }
+    }
+}
+
+pub struct Outcome {
+    pub variant: Variant,
+    pub expected: usize,
+    pub observed: usize,
+    pub elapsed: Duration,
+}
+
+impl Outcome {
+    pub fn lost(&self) -> usize {
+        self.expected - self.observed
+    }
+
+    // increments per second
+    pub fn throughput(&self) -> f64 {
+        self.expected as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} : expected {} observed {} ( lost {} ) in {:.1?}, {:.2} M increments / s",
+            self.variant.name(),
+            self.expected,
+            self.observed,
+            self.lost(),
+            self.elapsed,
+            self.throughput() / 1e6
+        )
+    }
+}
+
+pub fn stress(variant: Variant, threads: usize, iterations: usize) -> Outcome {
+    let counter = Counter::new(0);
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                for _ in 0..iterations {
+                    variant.increment(&counter);
+                }
+            });
+        }
+    });
+    let elapsed = start.elapsed();
+    let observed = counter.with_lock(|v| *v);
+    Outcome {
+        variant,
+        expected: threads * iterations,
+        observed,
+        elapsed,
+    }
+}
+
+// `cargo run --release -- stress [--threads N] [--iterations M]`
+pub fn run(threads: usize, iterations: usize) {
+    println!("{threads} threads x {iterations} increments");
+    for variant in Variant::ALL {
+        println!("{}", stress(variant, threads, iterations));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn with_lock_3_never_loses_updates() {
+        let outcome = stress(Variant::WithLock3, 4, 10_000);
+        assert_eq!(outcome.observed, outcome.expected, "{outcome}");
+    }
+}
@@ -0,0 +1,11 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Small unique number per thread that fits in an atomic ( std's ThreadId can't be read
+// as an integer on stable ). 0 is never handed out so it can mean "no thread".
+pub fn current() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(1);
+    thread_local! {
+        static ID: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    ID.with(|id| *id)
+}
@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::mutex::Mutex;
+use crate::raw::RawLock;
+
+// Fair lock, like the queue at the deli counter : take a number, wait until it's called.
+// Threads get the lock in the order they took their ticket ( FIFO ), nobody starves.
+pub type TicketLock<T> = Mutex<RawTicketLock, T>;
+
+pub struct RawTicketLock {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+}
+
+unsafe impl RawLock for RawTicketLock {
+    fn new() -> Self {
+        Self {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+        }
+    }
+
+    fn lock(&self) {
+        // Relaxed is fine, the ticket only decides our place in the queue
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        // Acquire pairs with the Release in unlock
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            std::hint::spin_loop();
+        }
+    }
+
+    // Only take a ticket if it would be served right away.
+    // A taken ticket can't be given back ( the holder would wait for us forever ),
+    // so timed attempts don't queue either, they use the default polling try_lock_until
+    fn try_lock(&self) -> bool {
+        // Acquire pairs with the Release in unlock, the CAS itself only grabs the number
+        let serving = self.now_serving.load(Ordering::Acquire);
+        self.next_ticket
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        // only the holder writes now_serving, so load + store is enough ( no RMW needed )
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        // Release pairs with the Acquire load of the next ticket holder
+        self.now_serving
+            .store(serving.wrapping_add(1), Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.next_ticket.load(Ordering::Relaxed) != self.now_serving.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::panic::Location;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex as StdMutex, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+use crate::thread_id;
+
+// Timeline of lock events for chrome://tracing / ui.perfetto.dev.
+// Recording is lock free : a fixed array of slots and one counter, each event takes
+// the next index with fetch_add and fills its slot. When the array is full new events
+// are dropped ( counted in `dropped` ) so tracing never slows down by allocating.
+
+#[cfg(not(test))]
+const CAPACITY: usize = 1 << 18;
+// `cargo test` runs every test in one process and the ones that lock a lot would fill
+// the buffer before the trace test gets its events in
+#[cfg(test)]
+const CAPACITY: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Kind {
+    WaitBegin = 1,
+    Acquired = 2,
+    Released = 3,
+}
+
+type Site = &'static Location<'static>;
+
+struct Slot {
+    ts_ns: AtomicU64,
+    tid: AtomicUsize,
+    lock: AtomicUsize,
+    site: AtomicPtr<Location<'static>>,
+    kind: AtomicU8,
+    // set last ( Release ) so the dump never sees a half written slot
+    ready: AtomicBool,
+}
+
+struct Buffer {
+    epoch: Instant,
+    next: AtomicUsize,
+    dropped: AtomicU64,
+    slots: Box<[Slot]>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Event {
+    pub ts_ns: u64,
+    pub tid: usize,
+    pub lock: usize,
+    pub site: Site,
+    pub kind: Kind,
+}
+
+// thread names for the metadata events, filled once per thread
+static THREAD_NAMES: StdMutex<Vec<(usize, String)>> = StdMutex::new(Vec::new());
+
+fn buffer() -> &'static Buffer {
+    static BUFFER: OnceLock<Buffer> = OnceLock::new();
+    BUFFER.get_or_init(|| Buffer {
+        epoch: Instant::now(),
+        next: AtomicUsize::new(0),
+        dropped: AtomicU64::new(0),
+        slots: (0..CAPACITY)
+            .map(|_| Slot {
+                ts_ns: AtomicU64::new(0),
+                tid: AtomicUsize::new(0),
+                lock: AtomicUsize::new(0),
+                site: AtomicPtr::new(ptr::null_mut()),
+                kind: AtomicU8::new(0),
+                ready: AtomicBool::new(false),
+            })
+            .collect(),
+    })
+}
+
+fn current_tid() -> usize {
+    thread_local! {
+        static TID: usize = {
+            let tid = thread_id::current();
+            let name = thread::current()
+                .name()
+                .map_or_else(|| format!("thread {tid}"), str::to_owned);
+            THREAD_NAMES.lock().unwrap_or_else(|e| e.into_inner()).push((tid, name));
+            tid
+        };
+    }
+    TID.with(|tid| *tid)
+}
+
+pub fn record(kind: Kind, lock: usize, site: Site) {
+    let buffer = buffer();
+    let ts_ns = buffer.epoch.elapsed().as_nanos() as u64;
+    let tid = current_tid();
+    let i = buffer.next.fetch_add(1, Ordering::Relaxed);
+    let Some(slot) = buffer.slots.get(i) else {
+        buffer.dropped.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    slot.ts_ns.store(ts_ns, Ordering::Relaxed);
+    slot.tid.store(tid, Ordering::Relaxed);
+    slot.lock.store(lock, Ordering::Relaxed);
+    slot.site
+        .store(site as *const _ as *mut _, Ordering::Relaxed);
+    slot.kind.store(kind as u8, Ordering::Relaxed);
+    slot.ready.store(true, Ordering::Release);
+}
+
+// Everything recorded so far ( events still being written are skipped )
+pub fn events() -> Vec<Event> {
+    let buffer = buffer();
+    let len = buffer.next.load(Ordering::Relaxed).min(CAPACITY);
+    let mut events: Vec<_> = buffer.slots[..len]
+        .iter()
+        // Acquire pairs with the Release of `ready` in record
+        .filter(|slot| slot.ready.load(Ordering::Acquire))
+        .map(|slot| Event {
+            ts_ns: slot.ts_ns.load(Ordering::Relaxed),
+            tid: slot.tid.load(Ordering::Relaxed),
+            lock: slot.lock.load(Ordering::Relaxed),
+            // Safety : only ever stored from a &'static Location
+            site: unsafe { &*slot.site.load(Ordering::Relaxed) },
+            kind: match slot.kind.load(Ordering::Relaxed) {
+                1 => Kind::WaitBegin,
+                2 => Kind::Acquired,
+                _ => Kind::Released,
+            },
+        })
+        .collect();
+    // slots are taken in fetch_add order, timestamps were read just before that
+    events.sort_by_key(|e| e.ts_ns);
+    events
+}
+
+pub fn dropped() -> u64 {
+    buffer().dropped.load(Ordering::Relaxed)
+}
+
+pub fn save(path: impl AsRef<Path>) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write_chrome_json(&mut w)?;
+    w.flush()
+}
+
+// Chrome trace format : every wait and every hold becomes one complete ("X") slice
+// on the thread's track, waits that never got the lock ( timed out ) are left out
+pub fn write_chrome_json(w: &mut impl Write) -> io::Result<()> {
+    let events = events();
+    writeln!(w, "{{\"displayTimeUnit\":\"ns\",\"traceEvents\":[")?;
+    let mut first = true;
+    let mut sep = |w: &mut dyn Write| -> io::Result<()> {
+        if !std::mem::take(&mut first) {
+            writeln!(w, ",")?;
+        }
+        Ok(())
+    };
+
+    for (tid, name) in THREAD_NAMES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+    {
+        sep(w)?;
+        write!(
+            w,
+            "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{tid},\"args\":{{\"name\":\"{}\"}}}}",
+            escape(name)
+        )?;
+    }
+
+    // (tid, lock) -> when the current wait / hold started
+    let mut waiting = HashMap::new();
+    let mut holding = HashMap::new();
+    for e in &events {
+        let key = (e.tid, e.lock);
+        let (name, start) = match e.kind {
+            Kind::WaitBegin => {
+                waiting.insert(key, e.ts_ns);
+                continue;
+            }
+            Kind::Acquired => {
+                holding.insert(key, e.ts_ns);
+                match waiting.remove(&key) {
+                    Some(start) => ("wait", start),
+                    None => continue, // try_lock, nothing to wait for
+                }
+            }
+            Kind::Released => match holding.remove(&key) {
+                Some(start) => ("hold", start),
+                None => continue,
+            },
+        };
+        sep(w)?;
+        write!(
+            w,
+            "{{\"name\":\"{name} {}\",\"cat\":\"lock\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3},\"args\":{{\"lock\":\"{:#x}\"}}}}",
+            escape(&e.site.to_string()),
+            e.tid,
+            start as f64 / 1000.0,
+            (e.ts_ns - start) as f64 / 1000.0,
+            e.lock
+        )?;
+    }
+    writeln!(w, "\n]}}")
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // JSON strings can't hold any other control character either
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mutex::{Mutex, TtasLock};
+
+    #[test]
+    fn every_wait_and_hold_becomes_a_slice() {
+        let m = Mutex::<TtasLock, usize>::new(0);
+        thread::scope(|s| {
+            for i in 0..4 {
+                let m = &m;
+                thread::Builder::new()
+                    .name(format!("locker\t{i}\n"))
+                    .spawn_scoped(s, move || {
+                        for _ in 0..100 {
+                            m.with_lock(|v| *v += 1);
+                        }
+                    })
+                    .unwrap();
+            }
+        });
+        let mut out = Vec::new();
+        write_chrome_json(&mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        // only the mutex above was created in this file, other tests trace their own
+        let slices = |name: &str| {
+            json.lines()
+                .filter(|l| l.contains(&format!("\"name\":\"{name} src/trace.rs:")))
+                .count()
+        };
+        assert_eq!(slices("wait"), 400);
+        assert_eq!(slices("hold"), 400);
+        assert!(json.contains("\"name\":\"locker\\t0\\n\""));
+    }
+
+    #[test]
+    fn escape_control_characters() {
+        assert_eq!(escape("a\"b\\c"), "a\\\"b\\\\c");
+        assert_eq!(escape("x\ny\tz\r\u{1}"), "x\\ny\\tz\\r\\u0001");
+    }
+}
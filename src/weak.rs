@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+
+use crate::model::{self, AtomicBool, Model};
+
+// Weak memory for the model checker ( model.rs ), roughly what C++ / Rust promise and
+// what ARM / POWER actually do :
+//
+// - every location keeps all the values ever stored to it, in one order everybody agrees
+//   on ( coherence ), think of it as the store buffers never quite draining
+// - each thread has a view : per location the oldest store it may still read. A load can
+//   return any store from there on ( the model checker tries all of them ) and moves the
+//   view up to what it read, so a thread never goes back in time on one location
+// - a Release store ( or one after a Release fence ) carries the writer's whole view,
+//   an Acquire load that reads it takes that view over : everything done before the
+//   Release is now visible. Relaxed carries nothing, so the data next to a lock can
+//   still be read stale after taking the lock
+// - RMWs ( CAS, swap, fetch_add ) always read the newest store, that's what keeps
+//   the lock word itself exclusive even with Relaxed
+// - SeqCst is approximated with one global view that SeqCst operations join
+//
+// x86 stores are all Release and loads all Acquire, so none of this shows up there.
+
+#[derive(Clone, Debug, Default)]
+pub struct View(BTreeMap<usize, usize>);
+
+impl View {
+    // location -> index of the oldest store we may read
+    pub fn get(&self, loc: usize) -> usize {
+        self.0.get(&loc).copied().unwrap_or(0)
+    }
+
+    fn raise(&mut self, loc: usize, ts: usize) {
+        let t = self.0.entry(loc).or_insert(0);
+        *t = (*t).max(ts);
+    }
+
+    fn join(&mut self, other: &View) {
+        for (&loc, &ts) in &other.0 {
+            self.raise(loc, ts);
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct ThreadView {
+    // what this thread has seen
+    cur: View,
+    // what an Acquire fence would add ( views of everything read Relaxed so far )
+    acq: View,
+    // what a Relaxed store carries ( view at the last Release fence )
+    rel: View,
+}
+
+pub struct Memory {
+    threads: Vec<ThreadView>,
+    sc: View,
+    // location -> newest store and the view it carries, for catch_up
+    latest: BTreeMap<usize, (usize, View)>,
+}
+
+fn is_acquire(order: Ordering) -> bool {
+    matches!(
+        order,
+        Ordering::Acquire | Ordering::AcqRel | Ordering::SeqCst
+    )
+}
+
+fn is_release(order: Ordering) -> bool {
+    matches!(
+        order,
+        Ordering::Release | Ordering::AcqRel | Ordering::SeqCst
+    )
+}
+
+impl Memory {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: vec![ThreadView::default(); threads],
+            sc: View::default(),
+            latest: BTreeMap::new(),
+        }
+    }
+
+    // Oldest store `me` may read at loc
+    pub fn visible_from(&mut self, me: usize, loc: usize, order: Ordering) -> usize {
+        let t = &mut self.threads[me];
+        if order == Ordering::SeqCst {
+            t.cur.join(&self.sc);
+        }
+        t.cur.get(loc)
+    }
+
+    // `me` read store ts at loc, which carried msg
+    pub fn load(&mut self, me: usize, loc: usize, ts: usize, msg: &View, order: Ordering) {
+        let t = &mut self.threads[me];
+        t.cur.raise(loc, ts);
+        t.acq.join(msg);
+        t.acq.raise(loc, ts);
+        if is_acquire(order) {
+            t.cur.join(msg);
+        }
+    }
+
+    // `me` stored ts at loc, returns the view the new store carries
+    pub fn store(&mut self, me: usize, loc: usize, ts: usize, order: Ordering) -> View {
+        let t = &mut self.threads[me];
+        if order == Ordering::SeqCst {
+            t.cur.join(&self.sc);
+        }
+        t.cur.raise(loc, ts);
+        t.acq.raise(loc, ts);
+        let mut msg = if is_release(order) {
+            t.cur.clone()
+        } else {
+            t.rel.clone()
+        };
+        msg.raise(loc, ts);
+        if order == Ordering::SeqCst {
+            self.sc.join(&t.cur);
+        }
+        self.latest.insert(loc, (ts, msg.clone()));
+        msg
+    }
+
+    // Read ts - 1 ( the newest ) and wrote ts. The new store also carries what the one it
+    // replaced did, so a Release followed by Relaxed RMWs still hands its view on
+    pub fn rmw(&mut self, me: usize, loc: usize, ts: usize, read: &View, order: Ordering) -> View {
+        self.load(me, loc, ts - 1, read, order);
+        let mut msg = self.store(me, loc, ts, order);
+        msg.join(read);
+        self.latest.insert(loc, (ts, msg.clone()));
+        msg
+    }
+
+    pub fn fence(&mut self, me: usize, order: Ordering) {
+        let t = &mut self.threads[me];
+        if is_acquire(order) {
+            t.cur.join(&t.acq);
+        }
+        if order == Ordering::SeqCst {
+            t.cur.join(&self.sc);
+            self.sc.join(&t.cur);
+        }
+        if is_release(order) {
+            t.rel = t.cur.clone();
+        }
+    }
+
+    // Stores become visible eventually : a spinning thread skips to the newest ones
+    // ( as if read Relaxed ) instead of waiting on a value nobody will write again.
+    // false if it was already up to date
+    pub fn catch_up(&mut self, me: usize) -> bool {
+        let t = &mut self.threads[me];
+        let mut moved = false;
+        for (&loc, (ts, msg)) in &self.latest {
+            if t.cur.get(loc) < *ts {
+                t.cur.raise(loc, *ts);
+                t.acq.join(msg);
+                moved = true;
+            }
+        }
+        moved
+    }
+}
+
+// The three with_lock steps with weak memory, `cargo run -- weak`
+pub fn run() {
+    let weak = Model {
+        weak: true,
+        ..Model::default()
+    };
+    for (name, with_lock) in [
+        (
+            "with_lock_2",
+            model::with_lock_2 as fn(&AtomicBool, &dyn Fn()),
+        ),
+        ("with_lock_3", model::with_lock_3),
+    ] {
+        println!("{}", model::check_with_lock(&weak, name, with_lock));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weak() -> Model {
+        Model {
+            weak: true,
+            ..Model::default()
+        }
+    }
+
+    #[test]
+    fn with_lock_2_reads_stale_data() {
+        let report = model::check_with_lock(&weak(), "with_lock_2", model::with_lock_2);
+        assert!(report.failure.is_some(), "{report}");
+    }
+
+    #[test]
+    fn with_lock_3_holds_on_weak_memory() {
+        let report = model::check_with_lock(&weak(), "with_lock_3", model::with_lock_3);
+        assert!(report.failure.is_none() && report.complete, "{report}");
+    }
+}